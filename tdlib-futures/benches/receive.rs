@@ -0,0 +1,42 @@
+//! Benchmarks of reading what TDLib sends: parsing responses and updates,
+//! and whole round trips through the client.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::executor::block_on;
+use futures::StreamExt;
+use std::thread;
+
+use tdlib_futures::client::init;
+use tdlib_futures::methods::*;
+use tdlib_futures::types::*;
+
+const RESPONSE: &str = r#"{"@type":"testInt","value":49,"@extra":7}"#;
+const UPDATE: &str = r#"{"@type":"updateOption","name":"version","value":{"@type":"optionValueString","value":"1.6.0"}}"#;
+
+fn parse(c: &mut Criterion) {
+    c.bench_function("response, single pass", |b| {
+        b.iter(|| TestSquareInt::read_response("testInt", RESPONSE).unwrap())
+    });
+    // What reading a response took before: a `Value` first, then the type.
+    c.bench_function("response, through Value", |b| {
+        b.iter(|| {
+            let value: serde_json::Value = serde_json::from_str(RESPONSE).unwrap();
+            serde_json::from_value::<TestInt>(value).unwrap()
+        })
+    });
+    c.bench_function("update", |b| {
+        b.iter(|| serde_json::from_str::<Update>(UPDATE).unwrap())
+    });
+}
+
+fn round_trip(c: &mut Criterion) {
+    let (sender, mut receiver, updater) = init();
+    thread::spawn(move || block_on(updater.drive()));
+    thread::spawn(move || block_on(async { while receiver.next().await.is_some() {} }));
+    c.bench_function("round trip", |b| {
+        b.iter(|| block_on(sender.send(TestSquareInt { x: 7 })).unwrap())
+    });
+}
+
+criterion_group!(benches, parse, round_trip);
+criterion_main!(benches);
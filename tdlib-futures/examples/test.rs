@@ -12,7 +12,7 @@ fn main() {
     tdlib_futures::set_log_verbosity_level(1);
 
     let mut pool = futures::executor::LocalPool::new();
-    let (mut sender, mut receiver, updater) = init();
+    let (sender, mut receiver, updater) = init();
     let spawner = pool.spawner();
     spawner.spawn(updater.drive()).expect("cannot spawn updater");
     let tdlib = TdlibParameters {
@@ -47,7 +47,7 @@ fn main() {
     //    getcode);
     let my_id: i32 = std::env::var("TG_BOT_ID").unwrap().parse().unwrap();
     pool.run_until(async move {
-        authorize(params, &mut sender, &mut receiver).await.expect("failed to authorize");
+        authorize(params, &sender, &mut receiver).await.expect("failed to authorize");
         loop {
             let update = dbg!(receiver.next().await);
             if let Some(Update::UpdateNewMessage(msg)) = update {
@@ -55,25 +55,12 @@ fn main() {
                     continue;
                 }
                 if let MessageContent::MessageText(text) = msg.message.content {
-                    let m = InputMessageText {
-                        text: FormattedText {
-                            text: format!("echo '{}'", text.text.text),
-                            entities: Vec::new(),
-                        },
-                        clear_draft: false,
-                        disable_web_page_preview: true,
-                    };
-                    let resp = SendMessage {
-                        chat_id: msg.message.chat_id,
-                        reply_to_message_id: msg.message.id,
-                        options: SendMessageOptions {
-                            disable_notification: false,
-                            from_background: false,
-                            scheduling_state: None,
-                        },
-                        reply_markup: None,
-                        input_message_content: InputMessageContent::InputMessageText(m),
-                    };
+                    let m = InputMessageText::builder(format!("echo '{}'", text.text.text))
+                        .disable_web_page_preview(true)
+                        .build();
+                    let resp = SendMessage::builder(msg.message.chat_id, m)
+                        .reply_to_message_id(msg.message.id)
+                        .build();
                     dbg!(sender.send(resp).await).ok();
                 }
             }
@@ -1,141 +1,394 @@
+use std::borrow::Cow;
 use std::sync::Arc;
-use futures::lock::Mutex;
-use std::sync::atomic::AtomicUsize;
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::Duration;
 use serde::{Serialize, Deserialize};
 use log::error;
 use futures::channel::{mpsc, oneshot};
-use futures::SinkExt;
+use futures::{SinkExt, StreamExt};
 
 use crate::types::*;
 use crate::methods::*;
+use crate::middleware::{Answer, Call, Middleware, Next};
+use crate::pending::{Pending, Reply};
+use crate::ratelimit::{RateLimiter, RateLimits};
+use crate::retry::RetryPolicy;
+use crate::runtime;
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct Request<T: Method> {
+struct Request<P> {
     #[serde(rename = "@extra")]
     id: usize,
     #[serde(flatten)]
-    payload: MethodType<T>,
+    payload: P,
 }
 
+/// What is read of a message before knowing what it is: whether it answers
+/// a request, and its type. Everything else is skipped without allocating.
 #[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-#[serde(untagged)]
-enum Message {
-    Response(Response),
-    Update(Update),
+struct Header<'a> {
+    #[serde(rename = "@extra")]
+    id: Option<usize>,
+    #[serde(rename = "@type", borrow, default)]
+    type_: Option<Cow<'a, str>>,
 }
+
+/// The chat a request is sent to, if any.
 #[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Response {
-    #[serde(rename = "@extra")]
-    id: usize,
-    #[serde(flatten)]
-    payload: serde_json::Value,
+struct Target {
+    chat_id: Option<i64>,
 }
 
+/// How long the receive thread waits for a message before checking whether
+/// it should stop.
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Creates a client. Its updater has to be driven for requests to be
+/// answered and updates to come. With the `rt-tokio` or `rt-async-std`
+/// feature, this must be called from within that runtime.
 pub fn init() -> (Sender, Receiver, Updater) {
     let (send, recv) = tdjson::Client::new().split();
     let (tx, rx) = mpsc::channel(256);
-    let pending = Arc::new(Mutex::new(HashMap::new()));
+    let (raw_tx, raw_rx) = mpsc::channel(256);
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        runtime::spawn_blocking(move || receive(recv, raw_tx, &stop))
+    };
+    let pending = Arc::new(Pending::new());
     let client = Sender {
-        tdclient: send,
-        pending: pending.clone(),
-        next_id: Arc::new(AtomicUsize::new(0)),
+        shared: Arc::new(Shared {
+            tdclient: send,
+            pending: pending.clone(),
+            next_id: AtomicUsize::new(0),
+        }),
+        retry: None,
+        limiter: None,
+        layers: Arc::new(Vec::new()),
     };
     let updater = Updater {
-        recv: Some(recv),
+        raw: raw_rx,
         tx,
+        raw_tx: None,
         pending,
+        stop,
+        thread: Some(thread),
     };
     (client, rx, updater)
 }
 
+/// A client whose updater runs as a task of the runtime chosen with the
+/// `rt-tokio` or `rt-async-std` feature.
+#[cfg(any(feature = "rt-tokio", feature = "rt-async-std"))]
+pub struct Client {
+    pub sender: Sender,
+    pub receiver: Receiver,
+    pub driver: runtime::Driver,
+}
+
+#[cfg(any(feature = "rt-tokio", feature = "rt-async-std"))]
+impl Client {
+    /// Creates a client and spawns the task driving its updater. It must be
+    /// called from within the runtime.
+    pub fn spawn() -> Client {
+        Client::spawn_with(|_| ())
+    }
+
+    /// Like `spawn`, giving the updater to `setup` before it is driven, e.g.
+    /// to take its `raw_updates`.
+    pub fn spawn_with<F: FnOnce(&mut Updater)>(setup: F) -> Client {
+        let (sender, receiver, mut updater) = init();
+        setup(&mut updater);
+        Client {
+            sender,
+            receiver,
+            driver: runtime::Driver::spawn(updater.drive()),
+        }
+    }
+}
+
+/// Body of the receive thread, which owns the receive client and forwards
+/// everything TDLib sends until it is told to stop or the updater is gone.
+fn receive(mut recv: tdjson::ReceiveClient, mut tx: mpsc::Sender<String>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        let raw = match recv.receive(RECEIVE_TIMEOUT) {
+            Some(raw) => raw.to_owned(),
+            None => continue,
+        };
+        if futures::executor::block_on(tx.send(raw)).is_err() {
+            break;
+        }
+    }
+}
+
 pub type Receiver = mpsc::Receiver<Update>;
+/// Updates as TDLib sent them, see `Updater::raw_updates`.
+pub type RawReceiver = mpsc::Receiver<serde_json::Value>;
 
+/// Sends requests to TDLib.
+///
+/// This is a handle over state shared with its clones and the updater:
+/// cloning it is cheap, and clones can be sent to and used from any thread.
+#[derive(Clone)]
 pub struct Sender {
+    shared: Arc<Shared>,
+    retry: Option<Arc<RetryPolicy>>,
+    limiter: Option<Arc<RateLimiter>>,
+    layers: Arc<Vec<Arc<dyn Middleware>>>,
+}
+struct Shared {
     tdclient: tdjson::SendClient,
-    pending: Arc<Mutex<HashMap<usize, oneshot::Sender<String>>>>,
-    next_id: Arc<AtomicUsize>,
+    pending: Arc<Pending>,
+    next_id: AtomicUsize,
 }
+
+/// Doesn't compile unless the sender can be shared across threads, and
+/// sending requests and driving the updater can run on any of them.
+#[allow(dead_code)]
+fn assert_thread_safe(sender: &Sender, updater: Updater) {
+    fn send_sync<T: Send + Sync>() {}
+    fn send<T: Send>(_: T) {}
+    send_sync::<Sender>();
+    send(sender.send(Close {}));
+    send(updater.drive());
+}
+
+/// Dispatches what TDLib sends to the pending requests and the update
+/// stream.
+///
+/// `drive` returns once the client is closed, e.g. after sending `Close`,
+/// which also ends the update stream. Dropping the updater stops the
+/// receive thread.
 pub struct Updater {
-    recv: Option<tdjson::ReceiveClient>,
+    raw: mpsc::Receiver<String>,
     tx: mpsc::Sender<Update>,
-    pending: Arc<Mutex<HashMap<usize, oneshot::Sender<String>>>>,
+    raw_tx: Option<mpsc::Sender<serde_json::Value>>,
+    pending: Arc<Pending>,
+    stop: Arc<AtomicBool>,
+    thread: Option<runtime::Blocking>,
 }
 impl Updater {
+    /// Returns a stream of all the updates as TDLib sent them, including
+    /// those of types that are missing from the schema or that can't be read
+    /// as an `Update`. The updater doesn't wait for it: updates arriving
+    /// while it is full are dropped from it and logged, and counted as
+    /// `tdlib_raw_updates_dropped_total` with the `metrics` feature.
+    pub fn raw_updates(&mut self) -> RawReceiver {
+        let (tx, rx) = mpsc::channel(256);
+        self.raw_tx = Some(tx);
+        rx
+    }
+
     pub async fn drive(mut self) {
-        let mut recv = self.recv.take();
-        loop {
-            let (raw, recv2) = blocking::unblock(move || {
-                let mut recv2 = recv.unwrap();
-                let raw = recv2.receive(Duration::from_secs(1)).map(|r|r.to_owned());
-                (raw, recv2)
-            }).await;
-            recv = Some(recv2);
-            let raw = match raw {
-                Some(raw) => raw,
-                None => continue,
+        while let Some(raw) = self.raw.next().await {
+            log::trace!("Updater received: {}", raw);
+            let header: Header = match serde_json::from_str(&raw) {
+                Ok(header) => header,
+                Err(e) => {
+                    error!("unhandled message: {}", raw);
+                    error!("reason: {:?}", e);
+                    continue;
+                }
             };
-            let mess: Result<Message, _> = serde_json::from_str(&raw);
-            match mess {
-                Ok(m) => {
-                    log::info!("Updater received: {:?}", m);
-                    match m {
-                        Message::Response(r) => {
-                            let mut map =  self.pending.lock().await;
-                            let tx = map.remove(&r.id);
-                            if let Some(tx) = tx {
-                                tx.send(raw.to_owned()).expect("canceled future");
-                            } else {
-                                log::error!("no request mapped for id {}", r.id);
-                            }
-                        }
-                        Message::Update(u) => {
-                            self.tx.send(u).await.expect("canceled future");
-                        }
+            let type_ = header.type_.as_deref().unwrap_or("");
+            if let Some(id) = header.id {
+                #[cfg(feature = "tracing")]
+                crate::trace::response(id, type_);
+                match self.pending.remove(id) {
+                    Some(reply) => reply(type_, &raw),
+                    // Its sender stopped waiting for it.
+                    None => log::debug!("no request mapped for id {}", id),
+                }
+                continue;
+            }
+            #[cfg(feature = "tracing")]
+            crate::trace::update(type_);
+            #[cfg(feature = "metrics")]
+            crate::metrics::update(type_);
+            if let Some(raw_tx) = &mut self.raw_tx {
+                let value = serde_json::from_str(&raw).expect("invalid json");
+                if let Err(e) = raw_tx.try_send(value) {
+                    if e.is_disconnected() {
+                        // Nobody reads them anymore.
+                        self.raw_tx = None;
+                    } else {
+                        log::warn!("raw update stream is full, dropping {}", type_);
+                        #[cfg(feature = "metrics")]
+                        crate::metrics::raw_dropped(type_);
                     }
                 }
+            }
+            let update: Update = match serde_json::from_str(&raw) {
+                Ok(update) => update,
                 Err(e) => {
                     error!("unhandled message: {}", raw);
-                    error!("reason: {:?}",e);
+                    error!("reason: {:?}", e);
                     continue;
                 }
+            };
+            if let Update::UpdateAuthorizationState(state) = &update {
+                if let AuthorizationState::AuthorizationStateClosed(_) = state.authorization_state {
+                    // Nothing comes after this.
+                    self.stop.store(true, Ordering::Relaxed);
+                }
             }
+            self.tx.send(update).await.expect("canceled future");
         }
+        // The channel is closed, so the thread is done.
+        if let Some(thread) = self.thread.take() {
+            runtime::join(thread).await;
+        }
+    }
+}
+impl Drop for Updater {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
     }
 }
 impl Sender {
-    pub async fn send<T: Method>(&self, data: T) -> Result<T::Response, Error> {
-        let id = self.next_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        let req = Request {
-            id,
-            payload: data.tag()
-        };
-        let s = serde_json::to_string(&req).expect("Cannot serialize");
-        let (tx, rx) = oneshot::channel();
-        {
-            let mut map = self.pending.lock().await;
-            map.insert(id, tx);
+    /// Returns a handle to the same client that retries requests failing
+    /// because of flood limits according to `policy`. This handle and its
+    /// other clones keep their own policy, if any.
+    pub fn with_retry(&self, policy: RetryPolicy) -> Sender {
+        Sender {
+            retry: Some(Arc::new(policy)),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a handle to the same client that holds the requests sending
+    /// messages within `limits`. This handle and its clones share the
+    /// limits, while other handles don't wait for them.
+    pub fn with_rate_limits(&self, limits: RateLimits) -> Sender {
+        Sender {
+            limiter: Some(Arc::new(RateLimiter::new(limits))),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a handle to the same client that passes requests through
+    /// `layer`, after the layers of this handle.
+    pub fn with_middleware<M: Middleware + 'static>(&self, layer: M) -> Sender {
+        let mut layers = (*self.layers).clone();
+        layers.push(Arc::new(layer));
+        Sender {
+            layers: Arc::new(layers),
+            ..self.clone()
         }
-        self.tdclient.send(&s);
-        let raw = rx.await.expect("canceled future");
-        match serde_json::from_str::<Response>(&raw) {
-            Ok(r) => {
-                let res = match serde_json::from_value::<T::Response>(r.payload) {
-                    Ok(ok) => ok,
-                    Err(_) => return Err(Error{code:-1, message: format!("cannot parse response: {}", raw)}),
-                };
-                Ok(res)
-            },
-            Err(_) => {
-                match serde_json::from_str(&raw) {
-                    Ok(ok) => Err(ok),
-                    Err(_) => Err(Error{code:-1, message: format!("cannot parse response: {}", raw)}),
+    }
+
+    pub async fn send<T: Method + 'static>(&self, data: T) -> MethodResult<T> {
+        let sending = async {
+            match &self.retry {
+                Some(policy) if policy.applies_to(T::TYPE) => {
+                    policy.run(T::TYPE, || self.send_once(data.clone())).await
                 }
+                _ => self.send_once(data).await,
+            }
+        };
+        #[cfg(feature = "metrics")]
+        let sending = crate::metrics::send(T::TYPE, sending);
+        #[cfg(feature = "tracing")]
+        let sending = crate::trace::send(T::TYPE, sending);
+        sending.await
+    }
+
+    /// Sends a call through the layers of this handle. Unlike `send`, it is
+    /// never retried.
+    pub async fn send_call(&self, call: Call) -> Answer {
+        Next::new(&self.layers, self).run(call).await
+    }
+
+    /// Sends a request built by hand, with its `@type`, returning the
+    /// response as it is. This is meant for methods that are newer than
+    /// the schema; the request goes through the layers and the rate limits
+    /// of the handle, but isn't retried.
+    pub async fn send_raw(&self, mut request: serde_json::Value) -> Result<serde_json::Value, Error> {
+        let method = request["@type"].as_str().unwrap_or_default().to_owned();
+        if let Some(request) = request.as_object_mut() {
+            // It would clash with the one matching the request to its answer.
+            request.remove("@extra");
+        }
+        let call = Call {
+            method: method.clone().into(),
+            payload: request,
+        };
+        let sending = async {
+            let answer = self.send_call(call).await;
+            if let Some(error) = answer.error() {
+                return Err(error);
+            }
+            let mut response: serde_json::Value = serde_json::from_str(&answer.json).map_err(|_| Error {
+                code: -1,
+                message: format!("cannot parse response: {}", answer.json),
+            })?;
+            if let Some(response) = response.as_object_mut() {
+                response.remove("@extra");
+            }
+            Ok(response)
+        };
+        #[cfg(feature = "metrics")]
+        let sending = crate::metrics::send(&method, sending);
+        #[cfg(feature = "tracing")]
+        let sending = crate::trace::send(&method, sending);
+        sending.await
+    }
+
+    async fn send_once<T: Method + 'static>(&self, data: T) -> MethodResult<T> {
+        if !self.layers.is_empty() {
+            let call = Call {
+                method: T::TYPE.into(),
+                payload: serde_json::to_value(data.tag()).expect("Cannot serialize"),
+            };
+            let answer = self.send_call(call).await;
+            return T::read_response(&answer.type_, &answer.json);
+        }
+        let (id, s) = self.request(data.tag());
+        let (tx, rx) = oneshot::channel();
+        // The response is read on the updater, straight into `T::Response`.
+        let reply: Reply = Box::new(move |type_, json| {
+            let _ = tx.send(T::read_response(type_, json));
+        });
+        self.submit(T::TYPE, id, s, reply, rx).await
+    }
+
+    /// Sends a call to TDLib, once it went through the layers.
+    pub(crate) async fn dispatch(&self, call: Call) -> Answer {
+        let (id, s) = self.request(call.payload);
+        let (tx, rx) = oneshot::channel();
+        let reply: Reply = Box::new(move |type_, json| {
+            let _ = tx.send(Answer {
+                type_: type_.to_owned(),
+                json: json.to_owned(),
+            });
+        });
+        self.submit(&call.method, id, s, reply, rx).await
+    }
+
+    /// Serializes a request, tagged with a new id.
+    fn request<P: Serialize>(&self, payload: P) -> (usize, String) {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "tracing")]
+        crate::trace::request(id);
+        let s = serde_json::to_string(&Request { id, payload }).expect("Cannot serialize");
+        (id, s)
+    }
+
+    /// Sends a request once the rate limits allow, and waits for `reply`
+    /// to hand over the answer through `answer`. If this future is dropped
+    /// first, the request stops being pending, and its answer is ignored.
+    async fn submit<R>(&self, method: &str, id: usize, s: String, reply: Reply, answer: oneshot::Receiver<R>) -> R {
+        if let Some(limiter) = &self.limiter {
+            if limiter.applies_to(method) {
+                let target: Target = serde_json::from_str(&s).expect("Cannot read back");
+                limiter.acquire(target.chat_id).await;
             }
         }
+        let entry = self.shared.pending.insert(id, reply);
+        self.shared.tdclient.send(&s);
+        let answer = answer.await.expect("canceled future");
+        entry.answered();
+        answer
     }
 }
@@ -1,8 +1,18 @@
 pub use tdjson::set_log_file;
 pub use tdjson::set_log_verbosity_level;
 
+pub use tdlib_types::TDLIB_VERSION;
 pub use tdlib_types::types as types;
 pub use tdlib_types::methods as methods;
 
 pub mod client;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod middleware;
+mod pending;
+pub mod ratelimit;
+pub mod retry;
+pub mod runtime;
+#[cfg(feature = "tracing")]
+mod trace;
 pub mod utils;
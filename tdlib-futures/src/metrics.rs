@@ -0,0 +1,121 @@
+//! Metrics of the client, for the `metrics` feature.
+//!
+//! They are reported through the `metrics` facade, to the recorder the
+//! application installs:
+//!
+//! - `tdlib_requests_total{method}`, the requests sent, by `@type`.
+//! - `tdlib_request_duration_seconds{method}`, how long requests took to be
+//!   answered, retries included.
+//! - `tdlib_request_errors_total{method, code}`, the requests that failed,
+//!   by the code of the error.
+//! - `tdlib_pending_requests`, the requests waiting for an answer.
+//! - `tdlib_updates_total{type}`, the updates received, by `@type`.
+//! - `tdlib_raw_updates_dropped_total{type}`, the updates left out of the
+//!   raw update stream because it was full.
+//!
+//! [`export_network_statistics`] adds TDLib's own count of the bytes it
+//! sent and received.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::client::Sender;
+use crate::methods::*;
+use crate::runtime;
+use crate::types::*;
+
+pub(crate) async fn send<R, F>(method: &str, sending: F) -> Result<R, Error>
+where
+    F: Future<Output = Result<R, Error>>,
+{
+    let method = method.to_owned();
+    ::metrics::counter!("tdlib_requests_total", "method" => method.clone()).increment(1);
+    let start = Instant::now();
+    let result = sending.await;
+    ::metrics::histogram!("tdlib_request_duration_seconds", "method" => method.clone())
+        .record(start.elapsed().as_secs_f64());
+    if let Err(error) = &result {
+        ::metrics::counter!(
+            "tdlib_request_errors_total",
+            "method" => method,
+            "code" => error.code.to_string()
+        )
+        .increment(1);
+    }
+    result
+}
+
+pub(crate) fn pending_added() {
+    ::metrics::gauge!("tdlib_pending_requests").increment(1.0);
+}
+
+pub(crate) fn pending_removed() {
+    ::metrics::gauge!("tdlib_pending_requests").decrement(1.0);
+}
+
+pub(crate) fn update(type_: &str) {
+    ::metrics::counter!("tdlib_updates_total", "type" => type_.to_owned()).increment(1);
+}
+
+pub(crate) fn raw_dropped(type_: &str) {
+    ::metrics::counter!("tdlib_raw_updates_dropped_total", "type" => type_.to_owned()).increment(1);
+}
+
+/// The `@type` of an object.
+fn type_name<T: Serialize>(object: &T) -> String {
+    let value = serde_json::to_value(object).expect("Cannot serialize");
+    value["@type"].as_str().unwrap_or_default().to_owned()
+}
+
+/// The entries of the statistics, read from their JSON so as not to depend
+/// on whether `NetworkStatistics` is a struct or, with
+/// `tdlib-types/polymorphic`, an enum.
+fn entries(statistics: &NetworkStatistics) -> Vec<NetworkStatisticsEntry> {
+    let value = serde_json::to_value(statistics).expect("Cannot serialize");
+    serde_json::from_value(value["entries"].clone()).unwrap_or_default()
+}
+
+/// Asks TDLib for its network statistics every `interval`, and reports
+/// them as the gauges `tdlib_network_sent_bytes{network, kind}` and
+/// `tdlib_network_received_bytes{network, kind}`. The `network` is the
+/// `@type` of the network type, and the `kind` that of the file type, or
+/// `call`.
+///
+/// This runs until it is dropped, so it is meant to be spawned.
+pub async fn export_network_statistics(sender: Sender, interval: Duration) {
+    loop {
+        match sender.send(GetNetworkStatistics { only_current: false }).await {
+            Ok(statistics) => {
+                for entry in &entries(&statistics) {
+                    let (network, kind, sent, received) = match entry {
+                        NetworkStatisticsEntry::NetworkStatisticsEntryFile(e) => {
+                            (&e.network_type, type_name(&e.file_type), e.sent_bytes, e.received_bytes)
+                        }
+                        NetworkStatisticsEntry::NetworkStatisticsEntryCall(e) => {
+                            (&e.network_type, "call".to_owned(), e.sent_bytes, e.received_bytes)
+                        }
+                        // Of a kind newer than the schema.
+                        _ => continue,
+                    };
+                    let network = type_name(network);
+                    ::metrics::gauge!(
+                        "tdlib_network_sent_bytes",
+                        "network" => network.clone(),
+                        "kind" => kind.clone()
+                    )
+                    .set(sent as f64);
+                    ::metrics::gauge!(
+                        "tdlib_network_received_bytes",
+                        "network" => network,
+                        "kind" => kind
+                    )
+                    .set(received as f64);
+                }
+            }
+            Err(e) => log::error!("cannot get network statistics: {:?}", e),
+        }
+        runtime::sleep(interval).await;
+    }
+}
@@ -0,0 +1,102 @@
+//! Layers of behaviour around every request.
+//!
+//! A [`Middleware`] added with
+//! [`Sender::with_middleware`](crate::client::Sender::with_middleware) is
+//! called with each request the handle sends, as a [`Call`], and the rest
+//! of the chain as [`Next`]. It can look at or change the call, pass it on
+//! any number of times or not at all, and look at or change the [`Answer`]
+//! before returning it.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use serde_json::Value;
+
+use crate::client::Sender;
+use crate::types::*;
+
+/// A request on its way to TDLib.
+#[derive(Clone, Debug)]
+pub struct Call {
+    /// The `@type` of the method, `Method::TYPE` for requests sent with
+    /// `send`. The answer is read as the response to this method.
+    pub method: Cow<'static, str>,
+    /// The request as it is sent, with its `@type` but without the `@extra`
+    /// that is added to match it with its answer.
+    pub payload: Value,
+}
+
+/// What TDLib answered to a request with.
+#[derive(Clone, Debug)]
+pub struct Answer {
+    /// The `@type` of the answer, which is `error` if the request failed.
+    pub type_: String,
+    /// The whole answer, as TDLib sent it.
+    pub json: String,
+}
+
+impl Answer {
+    /// Makes an answer out of an object with a `@type`, as a middleware
+    /// would to answer a call itself.
+    pub fn new(object: Value) -> Answer {
+        Answer {
+            type_: object["@type"].as_str().unwrap_or_default().to_owned(),
+            json: object.to_string(),
+        }
+    }
+
+    /// Reads the error the request failed with, if it did.
+    pub fn error(&self) -> Option<Error> {
+        if self.type_ == "error" {
+            serde_json::from_str(&self.json).ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// A layer around the requests of a sender. It gets the call and the rest
+/// of the chain, and returns the answer to the call.
+pub trait Middleware: Send + Sync {
+    fn call<'a>(&'a self, call: Call, next: Next<'a>) -> BoxFuture<'a, Answer>;
+}
+
+/// The layers after the current one, ending with sending the call to
+/// TDLib.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    sender: &'a Sender,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(layers: &'a [Arc<dyn Middleware>], sender: &'a Sender) -> Next<'a> {
+        Next { layers, sender }
+    }
+
+    pub fn run(self, call: Call) -> BoxFuture<'a, Answer> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.call(call, Next { layers, ..self }),
+            None => Box::pin(self.sender.dispatch(call)),
+        }
+    }
+}
+
+/// A sender is a service answering calls, going through its layers, so
+/// that it can be used with the middleware of the tower ecosystem.
+#[cfg(feature = "tower")]
+impl tower_service::Service<Call> for Sender {
+    type Response = Answer;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<'static, Result<Answer, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, call: Call) -> Self::Future {
+        let sender = self.clone();
+        Box::pin(async move { Ok(sender.send_call(call).await) })
+    }
+}
@@ -0,0 +1,88 @@
+//! The requests waiting for an answer, shared by the senders and the
+//! updater.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Mutex;
+
+/// Reads the answer to a request, given its `@type` and the whole message,
+/// and hands it over to whoever is waiting for it.
+pub(crate) type Reply = Box<dyn FnOnce(&str, &str) + Send>;
+
+/// Ids are handed out in sequence, so consecutive requests land in
+/// different shards.
+const SHARDS: usize = 16;
+
+/// A map from request ids to replies, split in shards each behind its own
+/// lock, so that concurrent requests rarely wait on each other. Locks are
+/// only held to insert or remove an entry, never across an await.
+pub(crate) struct Pending {
+    shards: Vec<Mutex<HashMap<usize, Reply>>>,
+}
+
+impl Pending {
+    pub(crate) fn new() -> Pending {
+        Pending {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    fn shard(&self, id: usize) -> &Mutex<HashMap<usize, Reply>> {
+        &self.shards[id % SHARDS]
+    }
+
+    /// Adds the reply to a request. It is removed again when the returned
+    /// entry is dropped before the request is answered.
+    pub(crate) fn insert(&self, id: usize, reply: Reply) -> Entry<'_> {
+        self.shard(id).lock().expect("poisoned pending map").insert(id, reply);
+        #[cfg(feature = "metrics")]
+        crate::metrics::pending_added();
+        Entry { pending: self, id }
+    }
+
+    pub(crate) fn remove(&self, id: usize) -> Option<Reply> {
+        let reply = self.shard(id).lock().expect("poisoned pending map").remove(&id);
+        #[cfg(feature = "metrics")]
+        {
+            if reply.is_some() {
+                crate::metrics::pending_removed();
+            }
+        }
+        reply
+    }
+}
+
+/// The entry of a request in the map, for as long as someone waits for the
+/// answer.
+#[must_use]
+pub(crate) struct Entry<'a> {
+    pending: &'a Pending,
+    id: usize,
+}
+
+impl Entry<'_> {
+    /// Leaves the entry be, as the updater already removed it.
+    pub(crate) fn answered(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for Entry<'_> {
+    fn drop(&mut self) {
+        self.pending.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_entries_are_removed() {
+        let pending = Pending::new();
+        drop(pending.insert(1, Box::new(|_, _| ())));
+        assert!(pending.remove(1).is_none());
+        pending.insert(2, Box::new(|_, _| ())).answered();
+        assert!(pending.remove(2).is_some());
+    }
+}
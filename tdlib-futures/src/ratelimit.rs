@@ -0,0 +1,425 @@
+//! Keeping outgoing messages within Telegram's limits.
+//!
+//! Bots may send around 30 messages per second overall, one per second to
+//! the same user and 20 per minute to the same group. Going over them gets
+//! requests refused with flood limit errors. [`RateLimits`] set with
+//! [`Sender::with_rate_limits`](crate::client::Sender::with_rate_limits)
+//! delays the requests sending messages so that they stay within the
+//! limits: their `send` only resolves once they were actually sent to
+//! TDLib and answered.
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::runtime;
+
+/// How many requests can be sent in how long. Up to `count` of them can go
+/// at once, as long as the average is kept.
+#[derive(Clone, Copy, Debug)]
+pub struct Rate {
+    pub count: u32,
+    pub per: Duration,
+}
+
+impl Rate {
+    pub fn new(count: u32, per: Duration) -> Rate {
+        Rate { count, per }
+    }
+
+    /// The time a token takes to come back.
+    fn interval(self) -> Duration {
+        self.per / self.count.max(1)
+    }
+}
+
+/// The limits to keep, and the methods they apply to.
+///
+/// Chats are told apart by the `chat_id` of the requests. By default those
+/// with a negative id are held to the group limit: TDLib gives positive ids
+/// to chats with users, and negative ones to basic groups, supergroups and
+/// channels, but also to secret chats. Bots, which the limits are about,
+/// have no secret chats; others can tell them apart with
+/// [`classify`](RateLimits::classify).
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    global: Rate,
+    private_chat: Rate,
+    group_chat: Rate,
+    is_group: fn(i64) -> bool,
+    methods: HashSet<&'static str>,
+}
+
+impl Default for RateLimits {
+    fn default() -> RateLimits {
+        RateLimits {
+            global: Rate::new(30, Duration::from_secs(1)),
+            private_chat: Rate::new(1, Duration::from_secs(1)),
+            group_chat: Rate::new(20, Duration::from_secs(60)),
+            is_group: |chat_id| chat_id < 0,
+            methods: [
+                "sendMessage",
+                "sendMessageAlbum",
+                "sendBotStartMessage",
+                "sendInlineQueryResultMessage",
+                "sendChatSetTtlMessage",
+                "forwardMessages",
+                "resendMessages",
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn new() -> RateLimits {
+        RateLimits::default()
+    }
+
+    /// Sets the limit for all the chats together.
+    pub fn global(mut self, rate: Rate) -> RateLimits {
+        self.global = rate;
+        self
+    }
+
+    /// Sets the limit for each chat with a user.
+    pub fn private_chat(mut self, rate: Rate) -> RateLimits {
+        self.private_chat = rate;
+        self
+    }
+
+    /// Sets the limit for each group or channel.
+    pub fn group_chat(mut self, rate: Rate) -> RateLimits {
+        self.group_chat = rate;
+        self
+    }
+
+    /// Sets which chats are held to the group limit rather than the private
+    /// chat one, by their id.
+    pub fn classify(mut self, is_group: fn(i64) -> bool) -> RateLimits {
+        self.is_group = is_group;
+        self
+    }
+
+    /// Applies the limits to the method with the given `@type` as well. It
+    /// needs to have a `chat_id`, or it is only held to the global limit.
+    pub fn limit(mut self, method: &'static str) -> RateLimits {
+        self.methods.insert(method);
+        self
+    }
+
+    /// Stops applying the limits to the method with the given `@type`.
+    pub fn unlimit(mut self, method: &'static str) -> RateLimits {
+        self.methods.remove(method);
+        self
+    }
+}
+
+/// A token bucket, kept as the time at which it would be full again: each
+/// request pushes it one interval further, and may go as long as that is
+/// less than a full bucket away.
+#[derive(Debug)]
+struct Bucket {
+    full_at: Instant,
+}
+
+impl Bucket {
+    fn new(now: Instant) -> Bucket {
+        Bucket { full_at: now }
+    }
+
+    /// Takes a token, returning when it is available.
+    fn reserve(&mut self, rate: Rate, now: Instant) -> Instant {
+        let interval = rate.interval();
+        let burst = interval * rate.count.max(1);
+        let start = self.full_at.max(now);
+        self.full_at = start + interval;
+        (start + interval).checked_sub(burst).map_or(now, |at| at.max(now))
+    }
+
+    /// Gives back a token that wasn't used.
+    fn refund(&mut self, rate: Rate) {
+        self.full_at = self.full_at.checked_sub(rate.interval()).unwrap_or(self.full_at);
+    }
+}
+
+/// The buckets of idle chats are dropped once every this many new chats.
+const MAX_IDLE_CHATS: usize = 1024;
+
+#[derive(Debug)]
+struct Buckets {
+    global: Bucket,
+    chats: HashMap<i64, Bucket>,
+    /// Chats added since idle ones were last dropped.
+    new_chats: usize,
+}
+
+/// Schedules requests according to some [`RateLimits`].
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: RateLimits) -> RateLimiter {
+        RateLimiter {
+            limits,
+            buckets: Mutex::new(Buckets {
+                global: Bucket::new(Instant::now()),
+                chats: HashMap::new(),
+                new_chats: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn applies_to(&self, method: &str) -> bool {
+        self.limits.methods.contains(method)
+    }
+
+    /// Waits until a request to `chat_id` can be sent.
+    ///
+    /// The chat's turn is taken first and the global one once it came, so
+    /// a chat that is waiting doesn't hold back the others. Requests to the
+    /// same chat are let through in the order they came. If the request is
+    /// dropped while waiting, the turns it took are given back.
+    pub(crate) async fn acquire(&self, chat_id: Option<i64>) {
+        let chat = chat_id.map(|chat_id| Turn {
+            limiter: self,
+            chat_id: Some(chat_id),
+            at: self.reserve_chat(chat_id, Instant::now()),
+        });
+        if let Some(chat) = &chat {
+            wait_until(chat.at).await;
+        }
+        let global = Turn {
+            limiter: self,
+            chat_id: None,
+            at: self.reserve_global(Instant::now()),
+        };
+        wait_until(global.at).await;
+        mem::forget(chat);
+        mem::forget(global);
+    }
+
+    fn rate(&self, chat_id: i64) -> Rate {
+        if (self.limits.is_group)(chat_id) {
+            self.limits.group_chat
+        } else {
+            self.limits.private_chat
+        }
+    }
+
+    fn reserve_chat(&self, chat_id: i64, now: Instant) -> Instant {
+        let mut buckets = self.buckets.lock().expect("poisoned rate limiter");
+        if !buckets.chats.contains_key(&chat_id) {
+            buckets.new_chats += 1;
+            if buckets.new_chats > MAX_IDLE_CHATS {
+                buckets.chats.retain(|_, bucket| bucket.full_at > now);
+                buckets.new_chats = 1;
+            }
+        }
+        let rate = self.rate(chat_id);
+        buckets
+            .chats
+            .entry(chat_id)
+            .or_insert_with(|| Bucket::new(now))
+            .reserve(rate, now)
+    }
+
+    fn reserve_global(&self, now: Instant) -> Instant {
+        let mut buckets = self.buckets.lock().expect("poisoned rate limiter");
+        buckets.global.reserve(self.limits.global, now)
+    }
+
+    /// Gives back the turn taken in the bucket of `chat_id`, or the global
+    /// one.
+    fn refund(&self, chat_id: Option<i64>) {
+        let mut buckets = self.buckets.lock().expect("poisoned rate limiter");
+        match chat_id {
+            Some(chat_id) => {
+                let rate = self.rate(chat_id);
+                if let Some(bucket) = buckets.chats.get_mut(&chat_id) {
+                    bucket.refund(rate);
+                }
+            }
+            None => buckets.global.refund(self.limits.global),
+        }
+    }
+}
+
+/// A turn taken by a request that is waiting for it, which is given back
+/// unless forgotten once it came.
+struct Turn<'a> {
+    limiter: &'a RateLimiter,
+    chat_id: Option<i64>,
+    at: Instant,
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        self.limiter.refund(self.chat_id);
+    }
+}
+
+async fn wait_until(at: Instant) {
+    let now = Instant::now();
+    if at > now {
+        runtime::sleep(at - now).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(
+            RateLimits::new()
+                .global(Rate::new(2, secs(1)))
+                .private_chat(Rate::new(1, secs(1)))
+                .group_chat(Rate::new(3, secs(60))),
+        )
+    }
+
+    #[test]
+    fn burst() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(now);
+        let rate = Rate::new(3, secs(3));
+        for _ in 0..3 {
+            assert_eq!(bucket.reserve(rate, now), now);
+        }
+        assert_eq!(bucket.reserve(rate, now), now + secs(1));
+    }
+
+    #[test]
+    fn steady_state() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(now);
+        let rate = Rate::new(3, secs(3));
+        for _ in 0..3 {
+            bucket.reserve(rate, now);
+        }
+        for i in 1..10 {
+            assert_eq!(bucket.reserve(rate, now), now + secs(i));
+        }
+        // Once idle for long enough, a whole burst can go again.
+        let later = now + secs(20);
+        for _ in 0..3 {
+            assert_eq!(bucket.reserve(rate, later), later);
+        }
+        assert_eq!(bucket.reserve(rate, later), later + secs(1));
+    }
+
+    #[test]
+    fn private_chats() {
+        let limiter = limiter();
+        let now = Instant::now();
+        assert_eq!(limiter.reserve_chat(1, now), now);
+        assert_eq!(limiter.reserve_chat(1, now), now + secs(1));
+        // Each chat has its own limit.
+        assert_eq!(limiter.reserve_chat(2, now), now);
+    }
+
+    #[test]
+    fn group_chats() {
+        let limiter = limiter();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.reserve_chat(-1, now), now);
+        }
+        assert_eq!(limiter.reserve_chat(-1, now), now + secs(20));
+        assert_eq!(limiter.reserve_chat(-2, now), now);
+    }
+
+    #[test]
+    fn classify() {
+        let limiter = RateLimiter::new(
+            RateLimits::new()
+                .private_chat(Rate::new(1, secs(1)))
+                .classify(|chat_id| chat_id < -1_000_000_000_000 && chat_id > -2_000_000_000_000),
+        );
+        let now = Instant::now();
+        // Secret chats have negative ids but are with a user.
+        let secret_chat = -2_000_000_000_001;
+        assert_eq!(limiter.reserve_chat(secret_chat, now), now);
+        assert_eq!(limiter.reserve_chat(secret_chat, now), now + secs(1));
+        let channel = -1_000_000_000_001;
+        assert_eq!(limiter.reserve_chat(channel, now), now);
+        assert_eq!(limiter.reserve_chat(channel, now), now);
+    }
+
+    #[test]
+    fn cancelled() {
+        #[cfg(feature = "rt-tokio")]
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        #[cfg(feature = "rt-tokio")]
+        let _runtime = runtime.enter();
+        let limiter = limiter();
+        let start = Instant::now();
+        assert_eq!(limiter.acquire(Some(1)).now_or_never(), Some(()));
+        // The next request to the chat has to wait, and gives its turn back
+        // when dropped.
+        assert_eq!(limiter.acquire(Some(1)).now_or_never(), None);
+        assert!(limiter.reserve_chat(1, start) < start + Duration::from_millis(1500));
+        // A request waiting for its global turn gives both back.
+        let later = Instant::now() + secs(10);
+        assert_eq!(limiter.reserve_global(later), later);
+        assert_eq!(limiter.acquire(Some(2)).now_or_never(), None);
+        assert_eq!(limiter.reserve_chat(2, later), later);
+        assert_eq!(limiter.reserve_global(later), later);
+    }
+
+    #[test]
+    fn global() {
+        let limiter = limiter();
+        let now = Instant::now();
+        assert_eq!(limiter.reserve_global(now), now);
+        assert_eq!(limiter.reserve_global(now), now);
+        assert_eq!(limiter.reserve_global(now), now + Duration::from_millis(500));
+    }
+
+    #[test]
+    fn pruning() {
+        let limiter = limiter();
+        let chats = || limiter.buckets.lock().unwrap().chats.len();
+        let now = Instant::now();
+        for chat_id in 0..MAX_IDLE_CHATS as i64 {
+            limiter.reserve_chat(chat_id, now);
+        }
+        assert_eq!(chats(), MAX_IDLE_CHATS);
+        // The chats aren't idle yet, so none is dropped.
+        let soon = now + Duration::from_millis(500);
+        limiter.reserve_chat(-1, soon);
+        assert_eq!(chats(), MAX_IDLE_CHATS + 1);
+        // They are idle now, but are only dropped once as many new chats
+        // came in again.
+        let later = now + secs(120);
+        limiter.reserve_chat(-2, later);
+        assert_eq!(chats(), MAX_IDLE_CHATS + 2);
+        // Only chats already known come in, which drops nothing.
+        limiter.reserve_chat(0, later);
+        assert_eq!(chats(), MAX_IDLE_CHATS + 2);
+        for chat_id in 0..MAX_IDLE_CHATS as i64 - 2 {
+            limiter.reserve_chat(-3 - chat_id, later);
+        }
+        assert_eq!(chats(), 2 * MAX_IDLE_CHATS);
+        // This one is, and only chat 0 and those added since the chats were
+        // idle are kept.
+        limiter.reserve_chat(i64::MIN, later);
+        assert_eq!(chats(), MAX_IDLE_CHATS + 1);
+    }
+}
@@ -0,0 +1,249 @@
+//! Retrying requests that hit Telegram's flood limits.
+//!
+//! When a request is sent too often, TDLib answers it with an error with
+//! code 429 telling how many seconds to wait before trying again. A
+//! [`RetryPolicy`] set with [`Sender::with_retry`](crate::client::Sender::with_retry)
+//! makes the sender wait and send the request again on its own.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::methods::*;
+use crate::runtime;
+use crate::types::*;
+
+/// Reads how long to wait before sending a request again out of the error
+/// it failed with, if it failed because of a flood limit.
+///
+/// TDLib reports those as `429` errors with a message like `Too Many
+/// Requests: retry after 5`, and some pass through as `FLOOD_WAIT_5`.
+pub fn retry_after(error: &Error) -> Option<Duration> {
+    let seconds = if error.code == 429 {
+        error.message.rsplit(' ').next()
+    } else {
+        error.message.strip_prefix("FLOOD_WAIT_")
+    };
+    seconds
+        .and_then(|s| s.parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// What the policy does about a failed request, as told to the hook.
+#[derive(Debug)]
+pub enum RetryEvent<'a> {
+    /// The request is sent again after `wait`. `attempt` counts the retries,
+    /// starting at 1.
+    Retrying {
+        method: &'static str,
+        attempt: usize,
+        wait: Duration,
+        error: &'a Error,
+    },
+    /// The request isn't retried anymore, because that would take too many
+    /// attempts or too long, and fails with `error`.
+    GivingUp {
+        method: &'static str,
+        retries: usize,
+        error: &'a Error,
+    },
+}
+
+type Hook = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+/// How requests failing because of flood limits are retried.
+///
+/// A request is retried after the time TDLib asks to wait, plus a random
+/// jitter so that requests limited together don't hit the server together
+/// again. It fails with the last error once it was retried `max_retries`
+/// times, or when the next wait would bring the total over `max_wait`.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    max_wait: Duration,
+    jitter: Duration,
+    skip: HashSet<&'static str>,
+    hook: Option<Hook>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            max_wait: Duration::from_secs(60),
+            jitter: Duration::from_secs(1),
+            skip: HashSet::new(),
+            hook: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    pub fn max_retries(mut self, max_retries: usize) -> RetryPolicy {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Caps the total time spent waiting to retry a request, jitter
+    /// included.
+    pub fn max_wait(mut self, max_wait: Duration) -> RetryPolicy {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Sets the most that is randomly added to each wait.
+    pub fn jitter(mut self, jitter: Duration) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Never retries the method `M`, whose errors are returned right away.
+    pub fn skip<M: Method>(mut self) -> RetryPolicy {
+        self.skip.insert(M::TYPE);
+        self
+    }
+
+    /// Calls `hook` on every retry and every time the policy gives up.
+    pub fn on_retry<F>(mut self, hook: F) -> RetryPolicy
+    where
+        F: Fn(&RetryEvent) + Send + Sync + 'static,
+    {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    pub(crate) fn applies_to(&self, method: &'static str) -> bool {
+        !self.skip.contains(method)
+    }
+
+    fn notify(&self, event: RetryEvent) {
+        if let Some(hook) = &self.hook {
+            hook(&event);
+        }
+    }
+
+    /// Runs `attempt` until it succeeds, fails for a reason other than a
+    /// flood limit, or the policy gives up.
+    pub(crate) async fn run<R, F, Fut>(&self, method: &'static str, mut attempt: F) -> Result<R, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        let mut retries = 0;
+        let mut waited = Duration::default();
+        loop {
+            let error = match attempt().await {
+                Err(error) => error,
+                ok => return ok,
+            };
+            let wait = match retry_after(&error) {
+                Some(wait) => wait + random_up_to(self.jitter),
+                None => return Err(error),
+            };
+            if retries == self.max_retries || waited + wait > self.max_wait {
+                self.notify(RetryEvent::GivingUp { method, retries, error: &error });
+                return Err(error);
+            }
+            retries += 1;
+            self.notify(RetryEvent::Retrying { method, attempt: retries, wait, error: &error });
+            runtime::sleep(wait).await;
+            waited += wait;
+        }
+    }
+}
+
+/// A random duration between zero and `max`, random enough to spread out
+/// retries without pulling in a random number generator.
+fn random_up_to(max: Duration) -> Duration {
+    let bits = RandomState::new().build_hasher().finish();
+    max.mul_f64((bits >> 11) as f64 / (1u64 << 53) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::Mutex;
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn error(code: i32, message: &str) -> Error {
+        Error {
+            code,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn too_many_requests() {
+        let wait = retry_after(&error(429, "Too Many Requests: retry after 5"));
+        assert_eq!(wait, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn flood_wait() {
+        assert_eq!(retry_after(&error(420, "FLOOD_WAIT_12")), Some(Duration::from_secs(12)));
+        assert_eq!(retry_after(&error(400, "FLOOD_WAIT_0")), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn no_wait() {
+        assert_eq!(retry_after(&error(429, "Too Many Requests: retry after")), None);
+        assert_eq!(retry_after(&error(429, "Too Many Requests: retry after soon")), None);
+        assert_eq!(retry_after(&error(429, "Too Many Requests: retry after -1")), None);
+        assert_eq!(retry_after(&error(429, "")), None);
+        assert_eq!(retry_after(&error(420, "FLOOD_WAIT_")), None);
+        assert_eq!(retry_after(&error(420, "FLOOD_WAIT_X")), None);
+        assert_eq!(retry_after(&error(420, "SLOWMODE_WAIT_5")), None);
+        // Only a 429 tells to retry after some time.
+        assert_eq!(retry_after(&error(400, "Too Many Requests: retry after 5")), None);
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let attempts = Cell::new(0);
+        let policy = RetryPolicy::new().on_retry(|event| panic!("unexpected {:?}", event));
+        let result: Result<(), Error> = block_on(policy.run("testReturnError", || {
+            attempts.set(attempts.get() + 1);
+            async { Err(error(400, "Bad Request: chat not found")) }
+        }));
+        assert_eq!(result.unwrap_err().code, 400);
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn successes_are_not_retried() {
+        let attempts = Cell::new(0);
+        let result = block_on(RetryPolicy::new().run("testSquareInt", || {
+            attempts.set(attempts.get() + 1);
+            async { Ok(4) }
+        }));
+        assert_eq!(result.unwrap(), 4);
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn gives_up() {
+        let retries = Arc::new(Mutex::new(Vec::new()));
+        let hook = retries.clone();
+        let policy = RetryPolicy::new()
+            .max_wait(Duration::from_secs(10))
+            .on_retry(move |event| match event {
+                RetryEvent::GivingUp { retries, .. } => hook.lock().unwrap().push(*retries),
+                RetryEvent::Retrying { .. } => panic!("unexpected {:?}", event),
+            });
+        let result: Result<(), Error> = block_on(policy.run("sendMessage", || async {
+            Err(error(429, "Too Many Requests: retry after 30"))
+        }));
+        assert_eq!(result.unwrap_err().code, 429);
+        assert_eq!(*retries.lock().unwrap(), vec![0]);
+    }
+}
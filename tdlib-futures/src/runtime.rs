@@ -0,0 +1,121 @@
+//! What the client needs from an async runtime: a place for the blocking
+//! receive loop, timers, and, for `Client::spawn`, spawning tasks.
+//!
+//! The `rt-tokio` and `rt-async-std` features use the runtime they are
+//! named after, which the client must then be created in. With neither, the
+//! client works on any executor, using the timers of `futures-timer`. Should
+//! both be enabled, tokio is used.
+//!
+//! The receive loop runs on a thread of its own, except with async-std,
+//! whose blocking threads come and go as needed. With tokio it would hold
+//! a thread of the blocking pool for as long as the client lives, and keep
+//! the runtime from shutting down until it is done.
+
+#[cfg(any(feature = "rt-tokio", feature = "rt-async-std"))]
+use std::future::Future;
+
+#[cfg(any(feature = "rt-tokio", not(feature = "rt-async-std")))]
+mod thread {
+    use std::thread;
+
+    pub(crate) type Blocking = thread::JoinHandle<()>;
+
+    pub(crate) fn spawn_blocking<F: FnOnce() + Send + 'static>(f: F) -> Blocking {
+        thread::Builder::new()
+            .name("tdlib-receive".to_owned())
+            .spawn(f)
+            .expect("cannot spawn the receive thread")
+    }
+
+    pub(crate) async fn join(blocking: Blocking) {
+        // The loop is done by the time this is called, so this doesn't
+        // block for long.
+        blocking.join().expect("receive thread panicked")
+    }
+}
+
+#[cfg(feature = "rt-tokio")]
+mod imp {
+    use std::future::Future;
+    use std::time::Duration;
+
+    pub(crate) use super::thread::*;
+
+    pub(crate) async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    pub(crate) type Task = tokio::task::JoinHandle<()>;
+
+    pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) -> Task {
+        tokio::spawn(future)
+    }
+
+    pub(crate) fn finished(result: Result<(), tokio::task::JoinError>) {
+        result.expect("updater panicked")
+    }
+}
+
+#[cfg(all(feature = "rt-async-std", not(feature = "rt-tokio")))]
+mod imp {
+    use std::future::Future;
+    use std::time::Duration;
+
+    pub(crate) type Blocking = async_std::task::JoinHandle<()>;
+
+    pub(crate) fn spawn_blocking<F: FnOnce() + Send + 'static>(f: F) -> Blocking {
+        async_std::task::spawn_blocking(f)
+    }
+
+    pub(crate) async fn join(blocking: Blocking) {
+        blocking.await
+    }
+
+    pub(crate) async fn sleep(duration: Duration) {
+        async_std::task::sleep(duration).await
+    }
+
+    pub(crate) type Task = async_std::task::JoinHandle<()>;
+
+    pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) -> Task {
+        async_std::task::spawn(future)
+    }
+
+    pub(crate) fn finished(_: ()) {}
+}
+
+#[cfg(not(any(feature = "rt-tokio", feature = "rt-async-std")))]
+mod imp {
+    use std::time::Duration;
+
+    pub(crate) use super::thread::*;
+
+    pub(crate) async fn sleep(duration: Duration) {
+        futures_timer::Delay::new(duration).await
+    }
+}
+
+pub(crate) use self::imp::*;
+
+/// The task driving the updater of a client started with `Client::spawn`,
+/// which resolves once the updater is done.
+#[cfg(any(feature = "rt-tokio", feature = "rt-async-std"))]
+pub struct Driver {
+    task: Task,
+}
+
+#[cfg(any(feature = "rt-tokio", feature = "rt-async-std"))]
+impl Driver {
+    pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) -> Driver {
+        Driver { task: spawn(future) }
+    }
+}
+
+#[cfg(any(feature = "rt-tokio", feature = "rt-async-std"))]
+impl Future for Driver {
+    type Output = ();
+
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context) -> std::task::Poll<()> {
+        std::pin::Pin::new(&mut self.task).poll(cx).map(finished)
+    }
+}
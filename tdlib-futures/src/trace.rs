@@ -0,0 +1,34 @@
+//! Spans and events for the `tracing` feature.
+
+use std::future::Future;
+use std::time::Instant;
+
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
+
+/// Runs the sending of a request in a span, with the `@type` of the method,
+/// the `@extra` id of the request once it has one, and how long it took to
+/// be answered. Retries of the request share the span, which keeps the id
+/// of the last one.
+pub(crate) async fn send<F: Future>(method: &str, sending: F) -> F::Output {
+    let span = tracing::debug_span!("send", method, id = Empty, latency_us = Empty);
+    let start = Instant::now();
+    let output = sending.instrument(span.clone()).await;
+    let latency_us = start.elapsed().as_micros() as u64;
+    span.record("latency_us", latency_us);
+    tracing::debug!(parent: &span, latency_us, "answered");
+    output
+}
+
+/// Records the id of the request being sent on its span.
+pub(crate) fn request(id: usize) {
+    Span::current().record("id", id as u64);
+}
+
+pub(crate) fn response(id: usize, type_: &str) {
+    tracing::debug!(id = id as u64, type_, "response");
+}
+
+pub(crate) fn update(type_: &str) {
+    tracing::debug!(type_, "update");
+}
@@ -57,7 +57,7 @@ macro_rules! wait_for_authorization_state {
         }
     }
 }
-pub async fn authorize(params: AuthParameters, sender: &mut Sender, receiver: &mut Receiver) -> Result<(), Error> {
+pub async fn authorize(params: AuthParameters, sender: &Sender, receiver: &mut Receiver) -> Result<(), Error> {
     wait_for_authorization_state!(receiver, AuthorizationStateWaitTdlibParameters);
     let s = SetTdlibParameters {
         parameters: params.tdlib
@@ -73,11 +73,7 @@ pub async fn authorize(params: AuthParameters, sender: &mut Sender, receiver: &m
         Credentials::User { phone, mut getcode } => {
             let s = SetAuthenticationPhoneNumber {
                 phone_number: phone,
-                settings: PhoneNumberAuthenticationSettings {
-                    allow_flash_call: false,
-                    is_current_phone_number: false,
-                    allow_sms_retriever_api: false,
-                },
+                settings: PhoneNumberAuthenticationSettings::default(),
             };
             sender.send(s).await?;
             wait_for_authorization_state!(receiver, AuthorizationStateWaitCode);
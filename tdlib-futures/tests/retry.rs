@@ -0,0 +1,82 @@
+//! Retrying requests through a sender, with a middleware standing in for
+//! TDLib and tokio's clock paused, so that waits take no time.
+#![cfg(feature = "rt-tokio")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use serde_json::json;
+use tdlib_futures::client::init;
+use tdlib_futures::methods::TestSquareInt;
+use tdlib_futures::middleware::{Answer, Call, Middleware, Next};
+use tdlib_futures::retry::RetryPolicy;
+use tdlib_futures::types::{Error, TestInt};
+
+/// Answers the first `floods` calls with a flood limit error asking to wait
+/// 5 seconds, and the others by squaring `x`.
+struct Flood {
+    floods: usize,
+    calls: Arc<AtomicUsize>,
+}
+
+impl Middleware for Flood {
+    fn call<'a>(&'a self, call: Call, _: Next<'a>) -> BoxFuture<'a, Answer> {
+        let answer = if self.calls.fetch_add(1, Ordering::SeqCst) < self.floods {
+            json!({"@type": "error", "code": 429, "message": "Too Many Requests: retry after 5"})
+        } else {
+            let x = call.payload["x"].as_i64().unwrap();
+            json!({"@type": "testInt", "value": x * x})
+        };
+        Box::pin(async move { Answer::new(answer) })
+    }
+}
+
+/// Sends a request answered after `floods` flood limit errors, returning
+/// the result, how many times it was sent and how long it took.
+fn send(floods: usize, policy: RetryPolicy) -> (Result<TestInt, Error>, usize, Duration) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let (sender, _receiver, _updater) = init();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let sender = sender
+            .with_retry(policy.jitter(Duration::from_secs(0)))
+            .with_middleware(Flood {
+                floods,
+                calls: calls.clone(),
+            });
+        let start = tokio::time::Instant::now();
+        let result = sender.send(TestSquareInt { x: 3 }).await;
+        (result, calls.load(Ordering::SeqCst), start.elapsed())
+    })
+}
+
+#[test]
+fn retries_after_waiting() {
+    let (result, calls, waited) = send(2, RetryPolicy::new());
+    assert_eq!(result.unwrap().value, 9);
+    assert_eq!(calls, 3);
+    assert_eq!(waited, Duration::from_secs(10));
+}
+
+#[test]
+fn stops_after_max_retries() {
+    let (result, calls, waited) = send(5, RetryPolicy::new().max_retries(2));
+    assert_eq!(result.unwrap_err().code, 429);
+    assert_eq!(calls, 3);
+    assert_eq!(waited, Duration::from_secs(10));
+}
+
+#[test]
+fn stops_before_max_wait() {
+    // A third wait would make it 15 seconds.
+    let (result, calls, waited) = send(5, RetryPolicy::new().max_wait(Duration::from_secs(12)));
+    assert_eq!(result.unwrap_err().code, 429);
+    assert_eq!(calls, 3);
+    assert_eq!(waited, Duration::from_secs(10));
+}
@@ -0,0 +1,35 @@
+//! A client spawned on tokio, which needs a TDLib to talk to.
+#![cfg(feature = "rt-tokio")]
+
+use futures::StreamExt;
+use tdlib_futures::client::Client;
+use tdlib_futures::methods::Close;
+
+#[test]
+fn raw_updates_of_a_spawned_client() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut raw = None;
+        let Client { sender, receiver, driver } =
+            Client::spawn_with(|updater| raw = Some(updater.raw_updates()));
+        let mut raw = raw.unwrap();
+        // Closing the client makes its authorization state change.
+        sender.send(Close {}).await.unwrap();
+        let mut closed = false;
+        while let Some(update) = raw.next().await {
+            if update["@type"] == "updateAuthorizationState"
+                && update["authorization_state"]["@type"] == "authorizationStateClosed"
+            {
+                closed = true;
+                break;
+            }
+        }
+        assert!(closed);
+        // The typed updates are kept until the updater is done with them.
+        driver.await;
+        drop(receiver);
+    });
+}
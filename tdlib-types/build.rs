@@ -2,20 +2,88 @@ extern crate tl_codegen;
 
 use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// TDLib version the vendored `td_api.tl` was taken from.
+const VENDORED_VERSION: &str = "1.6.0";
 
 fn main() {
-    let out_dir = env::var("OUT_DIR").unwrap();
-    let types_dest_path = Path::new(&out_dir).join("td_api_types.rs");
-    let methods_dest_path = Path::new(&out_dir).join("td_api_methods.rs");
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    println!("cargo:rerun-if-env-changed=TDLIB_TYPES_GENERATED_DIR");
+
+    // The generated code can be written to a directory of the user's choice,
+    // e.g. to check it in and review what a schema change does to the API.
+    // Relative paths are taken from the crate root.
+    let out_dir = match env::var_os("TDLIB_TYPES_GENERATED_DIR") {
+        Some(dir) => {
+            let dir = manifest_dir.join(dir);
+            fs::create_dir_all(&dir)
+                .unwrap_or_else(|e| panic!("cannot create {}: {}", dir.display(), e));
+            dir
+        }
+        None => PathBuf::from(env::var("OUT_DIR").unwrap()),
+    };
+    println!("cargo:rustc-env=TDLIB_TYPES_GENERATED_DIR={}", out_dir.display());
+    let types_dest_path = out_dir.join("td_api_types.rs");
+    let methods_dest_path = out_dir.join("td_api_methods.rs");
+    let version_dest_path = out_dir.join("td_api_version.rs");
+
+    println!("cargo:rerun-if-env-changed=TDLIB_TL_PATH");
+    println!("cargo:rerun-if-env-changed=TDLIB_TL_VERSION");
 
-    let src_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let src_path = Path::new(&src_dir).join("td_api.tl");
+    // A custom schema can be used to match the libtdjson the crate will be
+    // linked against; its version is taken from TDLIB_TL_VERSION. A relative
+    // path is taken from the crate root too, not from wherever cargo runs
+    // the build script.
+    let (src_path, version) = match env::var_os("TDLIB_TL_PATH") {
+        Some(path) => {
+            let version = env::var("TDLIB_TL_VERSION").unwrap_or_else(|_| "unknown".to_owned());
+            (manifest_dir.join(path), version)
+        }
+        None => (manifest_dir.join("td_api.tl"), VENDORED_VERSION.to_owned()),
+    };
 
     println!("cargo:rerun-if-changed={}",src_path.display());
 
-    let src = fs::read_to_string(src_path).expect("no td_api.tl file");
-    let (t, m) = tl_codegen::generate(&src);
-    fs::write(types_dest_path, t).expect("cannot write output file");
-    fs::write(methods_dest_path, m).expect("cannot write output file");
+    let src = fs::read_to_string(&src_path)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", src_path.display(), e));
+    let mut options = tl_codegen::CodegenOptions {
+        derives: vec![tl_codegen::Derive::Default],
+        domain_modules: true,
+        builders: true,
+        ..Default::default()
+    };
+    if env::var_os("CARGO_FEATURE_POLYMORPHIC").is_some() {
+        options.polymorphic_classes = true;
+    }
+    if env::var_os("CARGO_FEATURE_EQ").is_some() {
+        options.derives.push(tl_codegen::Derive::Eq);
+    }
+    if env::var_os("CARGO_FEATURE_HASH").is_some() {
+        options.derives.push(tl_codegen::Derive::Hash);
+    }
+    if env::var_os("CARGO_FEATURE_JSON_SCHEMA").is_some() {
+        options.derives.push(tl_codegen::Derive::JsonSchema);
+    }
+    let (t, m) = tl_codegen::generate_with_options(&src, &options);
+    write_if_changed(&types_dest_path, &t);
+    write_if_changed(&methods_dest_path, &m);
+    let version = format!(
+        "/// Version of TDLib whose `td_api.tl` the bindings were generated from.\n\
+         ///\n\
+         /// This is the vendored schema unless `TDLIB_TL_PATH` pointed the build at\n\
+         /// another one, in which case it is the value of `TDLIB_TL_VERSION`.\n\
+         pub const TDLIB_VERSION: &str = {:?};\n",
+        version
+    );
+    write_if_changed(&version_dest_path, &version);
+}
+
+/// Writes a file unless it already has the given contents, so that checked
+/// in code isn't touched by every build.
+fn write_if_changed(path: &Path, contents: &str) {
+    if fs::read_to_string(path).map(|c| c == contents).unwrap_or(false) {
+        return;
+    }
+    fs::write(path, contents).expect("cannot write output file");
 }
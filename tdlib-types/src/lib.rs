@@ -2,20 +2,32 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_aux;
+extern crate serde_json;
+#[cfg(feature = "json-schema")]
+extern crate schemars;
+
+include!(concat!(env!("TDLIB_TYPES_GENERATED_DIR"), "/td_api_version.rs"));
+
+mod text;
 
 pub mod types {
-include!(concat!(env!("OUT_DIR"), "/td_api_types.rs"));
+include!(concat!(env!("TDLIB_TYPES_GENERATED_DIR"), "/td_api_types.rs"));
 }
 
 pub mod methods {
 use super::types::*;
 use ::serde::de::DeserializeOwned;
 use ::serde::Serialize;
+use ::serde_json::Value;
 use ::std::fmt::Debug;
 
+/// What a call to the method `M` results in: its response, or the error
+/// TDLib returned instead.
+pub type MethodResult<M> = Result<<M as Method>::Response, Error>;
+
 pub trait Method: Serialize+Clone {
     const TYPE: &'static str;
-    type Response: DeserializeOwned+Debug;
+    type Response: DeserializeOwned+Debug+Send+'static;
 
     fn tag(self) -> MethodType<Self>
         where Self: ::std::marker::Sized {
@@ -24,6 +36,30 @@ pub trait Method: Serialize+Clone {
             payload: self,
         }
     }
+
+    /// Reads what TDLib answered to the method with, given the `@type` of
+    /// the answer, which is `error` if the call failed.
+    fn read_response(type_: &str, json: &str) -> MethodResult<Self> {
+        match type_ {
+            "error" => Err(Self::parse_response(json)?),
+            // `ok` carries no data, and is the answer of methods whose
+            // response is `()`.
+            "ok" => ::serde_json::from_value(Value::Null).map_err(|_| invalid_response(json)),
+            _ => Self::parse_response(json),
+        }
+    }
+
+    /// Reads an object, turning a failure into an `Error` with code -1.
+    fn parse_response<R: DeserializeOwned>(json: &str) -> Result<R, Error> {
+        ::serde_json::from_str(json).map_err(|_| invalid_response(json))
+    }
+}
+
+fn invalid_response(json: &str) -> Error {
+    Error {
+        code: -1,
+        message: format!("cannot parse response: {}", json),
+    }
 }
 #[derive(Serialize, Debug, Clone)]
 pub struct MethodType<T: Method> {
@@ -33,5 +69,5 @@ pub struct MethodType<T: Method> {
     pub payload: T,
 }
 
-include!(concat!(env!("OUT_DIR"), "/td_api_methods.rs"));
+include!(concat!(env!("TDLIB_TYPES_GENERATED_DIR"), "/td_api_methods.rs"));
 }
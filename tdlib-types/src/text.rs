@@ -0,0 +1,57 @@
+//! Conversions from plain text to the types that messages are sent with,
+//! so that builders can take a string, e.g.
+//! `SendMessage::builder(chat_id, "hi")`.
+
+use types::{FormattedText, InputMessageContent, InputMessageText};
+
+impl<'a> From<&'a str> for FormattedText {
+    fn from(text: &'a str) -> FormattedText {
+        FormattedText::from(text.to_owned())
+    }
+}
+
+#[cfg(not(feature = "polymorphic"))]
+impl From<String> for FormattedText {
+    fn from(text: String) -> FormattedText {
+        FormattedText {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
+/// With `polymorphic`, `FormattedText` is an enum of its one constructor.
+#[cfg(feature = "polymorphic")]
+impl From<String> for FormattedText {
+    fn from(text: String) -> FormattedText {
+        ::types::FormattedTextData {
+            text,
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl From<FormattedText> for InputMessageText {
+    fn from(text: FormattedText) -> InputMessageText {
+        InputMessageText::builder(text).build()
+    }
+}
+
+impl<'a> From<&'a str> for InputMessageContent {
+    fn from(text: &'a str) -> InputMessageContent {
+        InputMessageText::from(FormattedText::from(text)).into()
+    }
+}
+
+impl From<String> for InputMessageContent {
+    fn from(text: String) -> InputMessageContent {
+        InputMessageText::from(FormattedText::from(text)).into()
+    }
+}
+
+impl From<FormattedText> for InputMessageContent {
+    fn from(text: FormattedText) -> InputMessageContent {
+        InputMessageText::from(text).into()
+    }
+}
@@ -0,0 +1,94 @@
+//! Generates bindings for a tl file inline, as an alternative to calling
+//! `tl_codegen::generate` from a build script.
+//!
+//! ```ignore
+//! use tdlib_types::methods::{Method, MethodResult};
+//!
+//! tl_codegen_macros::tl_include!("tl/custom_api.tl");
+//! ```
+//!
+//! expands to a `types` and a `methods` module. The generated methods
+//! implement the `Method` trait in scope where the macro is invoked, and
+//! refer to the `MethodResult` alias next to it, so that
+//! they can be sent with `tdlib_futures::client::Sender`. The crate must
+//! depend on `serde` (with the `derive` feature), `serde_json` and
+//! `serde-aux`.
+
+extern crate proc_macro;
+
+use proc_macro::{TokenStream, TokenTree};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn error(message: &str) -> TokenStream {
+    format!("compile_error!({:?});", message).parse().unwrap()
+}
+
+/// Generates the `types` and `methods` modules for the tl file at the given
+/// path, relative to the root of the crate.
+#[proc_macro]
+pub fn tl_include(input: TokenStream) -> TokenStream {
+    let tokens = input.into_iter().collect::<Vec<_>>();
+    let path = match tokens.as_slice() {
+        [TokenTree::Literal(lit)] => {
+            let lit = lit.to_string();
+            if lit.len() < 2 || !lit.starts_with('"') || !lit.ends_with('"') || lit.contains('\\') {
+                return error("tl_include! expects a plain string literal");
+            }
+            lit[1..lit.len() - 1].to_owned()
+        }
+        _ => return error("tl_include! expects the path of a tl file"),
+    };
+    let root = env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    match expand(Path::new(&root), &path) {
+        Ok(code) => code.parse().unwrap(),
+        Err(message) => error(&message),
+    }
+}
+
+/// The code for the tl file at `path` relative to `root`, or why there is
+/// none.
+fn expand(root: &Path, path: &str) -> Result<String, String> {
+    let full_path = root.join(path);
+    let src = fs::read_to_string(&full_path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    tl_codegen::parse(&src).map_err(|e| format!("cannot parse {}: {}", path, e))?;
+    // The code is parsed again by the compiler, so it needn't be formatted.
+    let options = tl_codegen::CodegenOptions {
+        pretty: false,
+        ..Default::default()
+    };
+    let (types, methods) = tl_codegen::generate_with_options(&src, &options);
+    // The include_str! makes cargo rebuild the crate when the file changes.
+    Ok(format!(
+        "const _: &str = include_str!({:?});
+        pub mod types {{
+            use ::serde::{{Serialize, Deserialize}};
+            {}
+        }}
+        pub mod methods {{
+            use ::serde::{{Serialize, Deserialize}};
+            use super::{{Method, MethodResult}};
+            use super::types::*;
+            {}
+        }}",
+        full_path.display().to_string(),
+        types,
+        methods,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(path: &str) -> Result<String, String> {
+        super::expand(Path::new(env!("CARGO_MANIFEST_DIR")), path)
+    }
+
+    #[test]
+    fn parse_error() {
+        let error = expand("tests/fixtures/invalid.tl").unwrap_err();
+        assert!(error.starts_with("cannot parse tests/fixtures/invalid.tl: "), "{}", error);
+    }
+}
@@ -0,0 +1,43 @@
+//! Using the bindings `tl_include!` generates.
+
+use tdlib_types::methods::{Method, MethodResult};
+
+tl_codegen_macros::tl_include!("tests/fixtures/pets.tl");
+
+use methods::{GetPet, PetAnimal};
+use types::{Animal, AnimalCat, Pet};
+
+#[test]
+fn types() {
+    let json = r#"{"@type":"animalCat","name":"Tom","lives":"9"}"#;
+    let animal: Animal = serde_json::from_str(json).unwrap();
+    let cat = AnimalCat {
+        name: "Tom".to_owned(),
+        lives: 9,
+    };
+    assert_eq!(animal, Animal::AnimalCat(cat.clone()));
+    assert_eq!(animal.name(), Some(&"Tom".to_owned()));
+    let json = serde_json::to_value(Animal::from(cat)).unwrap();
+    assert_eq!(json, serde_json::json!({"@type": "animalCat", "name": "Tom", "lives": 9}));
+}
+
+#[test]
+fn methods() {
+    let request = GetPet { name: "Tom".to_owned() }.tag();
+    let json = serde_json::to_value(&request).unwrap();
+    assert_eq!(json, serde_json::json!({"@type": "getPet", "name": "Tom"}));
+    assert_eq!(PetAnimal::TYPE, "petAnimal");
+}
+
+#[test]
+fn responses() {
+    let json = r#"{"@type":"pet","animal":{"@type":"animalDog","name":"Rex"},"since":1}"#;
+    let pet: MethodResult<GetPet> = GetPet::read_response("pet", json);
+    let pet: Pet = pet.unwrap();
+    assert_eq!(pet.animal.name(), Some(&"Rex".to_owned()));
+    assert_eq!(pet.since, 1);
+    let ok: MethodResult<PetAnimal> = PetAnimal::read_response("ok", r#"{"@type":"ok"}"#);
+    assert_eq!(ok, Ok(()));
+    let json = r#"{"@type":"error","code":404,"message":"Not Found"}"#;
+    assert_eq!(PetAnimal::read_response("error", json).unwrap_err().code, 404);
+}
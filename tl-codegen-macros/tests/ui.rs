@@ -0,0 +1,6 @@
+//! Errors `tl_include!` reports at compile time.
+
+#[test]
+fn ui() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
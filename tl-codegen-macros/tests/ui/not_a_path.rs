@@ -0,0 +1,3 @@
+tl_codegen_macros::tl_include!(pets);
+
+fn main() {}
@@ -0,0 +1,51 @@
+//! Prints the differences between two tl files.
+//!
+//! Usage: `tl-diff [--json] OLD.tl NEW.tl`
+//!
+//! Like `diff`, exits with status 0 when the schemas are equivalent, 1 when
+//! they differ and 2 on errors.
+
+extern crate serde_json;
+extern crate tl_codegen;
+
+use std::env;
+use std::fs;
+use std::process;
+
+fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("cannot read {}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn main() {
+    let mut json = false;
+    let mut paths = Vec::new();
+    for arg in env::args().skip(1) {
+        if arg == "--json" {
+            json = true;
+        } else {
+            paths.push(arg);
+        }
+    }
+    if paths.len() != 2 {
+        eprintln!("usage: tl-diff [--json] OLD.tl NEW.tl");
+        process::exit(2);
+    }
+    let changes = match tl_codegen::diff::diff(&read(&paths[0]), &read(&paths[1])) {
+        Ok(changes) => changes,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changes).expect("cannot serialize"));
+    } else {
+        for change in &changes {
+            println!("{}", change);
+        }
+    }
+    process::exit(if changes.is_empty() { 0 } else { 1 });
+}
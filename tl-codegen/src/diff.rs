@@ -0,0 +1,245 @@
+//! Comparison between two versions of a tl schema, e.g. to review what a
+//! TDLib upgrade changes before regenerating the bindings.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use schema::{self, Definition, Param, ParseError};
+
+/// A single difference between two schemas.
+///
+/// Types and methods are identified by their tl name, fields by the tl name
+/// of the type or method they belong to (`owner`) and their own name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    TypeAdded { name: String },
+    TypeRemoved { name: String },
+    MethodAdded { name: String },
+    MethodRemoved { name: String },
+    /// A constructor now belongs to a different class.
+    ClassChanged { name: String, old: String, new: String },
+    ReturnTypeChanged { name: String, old: String, new: String },
+    FieldAdded { owner: String, name: String, typeid: String },
+    FieldRemoved { owner: String, name: String },
+    FieldTypeChanged { owner: String, name: String, old: String, new: String },
+    FieldOptionalityChanged { owner: String, name: String, optional: bool },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::TypeAdded { name } => write!(f, "+ type {}", name),
+            Change::TypeRemoved { name } => write!(f, "- type {}", name),
+            Change::MethodAdded { name } => write!(f, "+ method {}", name),
+            Change::MethodRemoved { name } => write!(f, "- method {}", name),
+            Change::ClassChanged { name, old, new } => {
+                write!(f, "~ type {}: class {} -> {}", name, old, new)
+            }
+            Change::ReturnTypeChanged { name, old, new } => {
+                write!(f, "~ method {}: returns {} -> {}", name, old, new)
+            }
+            Change::FieldAdded { owner, name, typeid } => {
+                write!(f, "+ field {}.{}: {}", owner, name, typeid)
+            }
+            Change::FieldRemoved { owner, name } => write!(f, "- field {}.{}", owner, name),
+            Change::FieldTypeChanged { owner, name, old, new } => {
+                write!(f, "~ field {}.{}: {} -> {}", owner, name, old, new)
+            }
+            Change::FieldOptionalityChanged { owner, name, optional } => write!(
+                f,
+                "~ field {}.{}: now {}",
+                owner,
+                name,
+                if *optional { "optional" } else { "required" }
+            ),
+        }
+    }
+}
+
+/// Compares two tl files, listing the changes needed to go from `old` to
+/// `new`.
+pub fn diff(old: &str, new: &str) -> Result<Vec<Change>, ParseError> {
+    let old = schema::parse(old)?;
+    let new = schema::parse(new)?;
+    let mut changes = Vec::new();
+    diff_definitions(&old.types, &new.types, false, &mut changes);
+    diff_definitions(&old.methods, &new.methods, true, &mut changes);
+    Ok(changes)
+}
+
+fn diff_definitions(
+    old: &[Definition],
+    new: &[Definition],
+    methods: bool,
+    changes: &mut Vec<Change>,
+) {
+    let new_by_name: HashMap<&str, &Definition> =
+        new.iter().map(|d| (d.name.as_str(), d)).collect();
+    let old_by_name: HashMap<&str, &Definition> =
+        old.iter().map(|d| (d.name.as_str(), d)).collect();
+    for o in old {
+        let n = match new_by_name.get(o.name.as_str()) {
+            Some(n) => n,
+            None => {
+                let name = o.name.clone();
+                changes.push(if methods {
+                    Change::MethodRemoved { name }
+                } else {
+                    Change::TypeRemoved { name }
+                });
+                continue;
+            }
+        };
+        if o.result != n.result {
+            let (name, old, new) = (o.name.clone(), o.result.clone(), n.result.clone());
+            changes.push(if methods {
+                Change::ReturnTypeChanged { name, old, new }
+            } else {
+                Change::ClassChanged { name, old, new }
+            });
+        }
+        diff_params(&o.name, &o.params, &n.params, changes);
+    }
+    for n in new {
+        if !old_by_name.contains_key(n.name.as_str()) {
+            let name = n.name.clone();
+            changes.push(if methods {
+                Change::MethodAdded { name }
+            } else {
+                Change::TypeAdded { name }
+            });
+        }
+    }
+}
+
+fn diff_params(owner: &str, old: &[Param], new: &[Param], changes: &mut Vec<Change>) {
+    for o in old {
+        let n = match new.iter().find(|n| n.name == o.name) {
+            Some(n) => n,
+            None => {
+                changes.push(Change::FieldRemoved {
+                    owner: owner.to_owned(),
+                    name: o.name.clone(),
+                });
+                continue;
+            }
+        };
+        if o.typeid != n.typeid {
+            changes.push(Change::FieldTypeChanged {
+                owner: owner.to_owned(),
+                name: o.name.clone(),
+                old: o.typeid.to_string(),
+                new: n.typeid.to_string(),
+            });
+        }
+        if o.optional != n.optional {
+            changes.push(Change::FieldOptionalityChanged {
+                owner: owner.to_owned(),
+                name: o.name.clone(),
+                optional: n.optional,
+            });
+        }
+    }
+    for n in new {
+        if !old.iter().any(|o| o.name == n.name) {
+            changes.push(Change::FieldAdded {
+                owner: owner.to_owned(),
+                name: n.name.clone(),
+                typeid: n.typeid.to_string(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = include_str!("../tests/fixtures/old.tl");
+    const NEW: &str = include_str!("../tests/fixtures/new.tl");
+
+    #[test]
+    fn identical() {
+        assert_eq!(diff(OLD, OLD).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn types_and_methods() {
+        let changes = diff(OLD, NEW).unwrap();
+        for change in &[
+            Change::TypeRemoved { name: "oldThing".to_owned() },
+            Change::TypeAdded { name: "newThing".to_owned() },
+            Change::MethodRemoved { name: "oldMethod".to_owned() },
+            Change::MethodAdded { name: "newMethod".to_owned() },
+            Change::ReturnTypeChanged {
+                name: "getUserPhoto".to_owned(),
+                old: "Photo".to_owned(),
+                new: "User".to_owned(),
+            },
+        ] {
+            assert!(changes.contains(change), "missing {}", change);
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let changes: Vec<_> = diff(OLD, NEW)
+            .unwrap()
+            .into_iter()
+            .filter(|c| {
+                matches!(
+                    c,
+                    Change::FieldAdded { .. }
+                        | Change::FieldRemoved { .. }
+                        | Change::FieldTypeChanged { .. }
+                        | Change::FieldOptionalityChanged { .. }
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                Change::FieldTypeChanged {
+                    owner: "user".to_owned(),
+                    name: "id".to_owned(),
+                    old: "int32".to_owned(),
+                    new: "int53".to_owned(),
+                },
+                Change::FieldOptionalityChanged {
+                    owner: "user".to_owned(),
+                    name: "photo".to_owned(),
+                    optional: false,
+                },
+                Change::FieldRemoved {
+                    owner: "user".to_owned(),
+                    name: "status".to_owned(),
+                },
+                Change::FieldAdded {
+                    owner: "user".to_owned(),
+                    name: "bio".to_owned(),
+                    typeid: "string".to_owned(),
+                },
+            ]
+        );
+    }
+
+    /// Changes come in the order of the old schema, then what the new one
+    /// adds, so the output of two runs can be diffed itself.
+    #[test]
+    fn json_order() {
+        let json = serde_json::to_string(&diff(OLD, NEW).unwrap()).unwrap();
+        let expected = [
+            r#"{"change":"field_type_changed","owner":"user","name":"id","old":"int32","new":"int53"}"#,
+            r#"{"change":"field_optionality_changed","owner":"user","name":"photo","optional":false}"#,
+            r#"{"change":"field_removed","owner":"user","name":"status"}"#,
+            r#"{"change":"field_added","owner":"user","name":"bio","typeid":"string"}"#,
+            r#"{"change":"type_removed","name":"oldThing"}"#,
+            r#"{"change":"type_added","name":"newThing"}"#,
+            r#"{"change":"return_type_changed","name":"getUserPhoto","old":"Photo","new":"User"}"#,
+            r#"{"change":"method_removed","name":"oldMethod"}"#,
+            r#"{"change":"method_added","name":"newMethod"}"#,
+        ];
+        assert_eq!(json, format!("[{}]", expected.join(",")));
+    }
+}
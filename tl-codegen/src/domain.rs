@@ -0,0 +1,245 @@
+//! Grouping of classes, methods and updates by the part of the API they
+//! belong to, so that each group can be put behind a cargo feature.
+
+use std::collections::HashMap;
+
+use schema::{Schema, TypeRef};
+
+/// The groups, each with the words of the names that belong to it. A name
+/// goes to the first group it has a word of. The group names are the names
+/// of the cargo features the generated code refers to.
+pub const DOMAINS: &[(&str, &[&str])] = &[
+    ("passport", &["passport", "personal", "identity", "encrypted", "dated"]),
+    ("payments", &["payment", "payments", "invoice", "shipping", "price", "credentials", "bank"]),
+    ("calls", &["call", "calls"]),
+    ("stickers", &["sticker", "stickers", "mask", "masks"]),
+    ("auth", &["authentication", "authorization", "password", "recovery"]),
+    ("proxy", &["proxy", "proxies", "network"]),
+    ("notifications", &["notification", "notifications"]),
+    ("bots", &["bot", "bots", "callback", "inline", "game", "games"]),
+    ("files", &["file", "files", "download", "upload", "uploaded"]),
+    ("users", &["user", "users", "contact", "contacts", "profile"]),
+    ("messages", &["message", "messages", "poll", "reply", "keyboard"]),
+    ("chats", &["chat", "chats", "supergroup", "group", "secret", "basic"]),
+];
+
+/// The class whose constructors are grouped one by one rather than with
+/// their class, as each update is about a different part of the API.
+pub const UPDATES: &str = "Update";
+
+/// The groups of what isn't shared between groups.
+#[derive(Debug, Default)]
+pub struct Domains {
+    /// Group of each class and method.
+    pub items: HashMap<String, &'static str>,
+    /// Group of each constructor of [`UPDATES`].
+    pub updates: HashMap<String, &'static str>,
+}
+
+/// Splits a camelCase tl name into lowercase words.
+fn words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for c in name.chars() {
+        if c.is_uppercase() || words.is_empty() {
+            words.push(String::new());
+        }
+        words.last_mut().unwrap().extend(c.to_lowercase());
+    }
+    words
+}
+
+fn guess(name: &str) -> Option<&'static str> {
+    let words = words(name);
+    DOMAINS
+        .iter()
+        .find(|(_, keywords)| words.iter().any(|w| keywords.contains(&w.as_str())))
+        .map(|(domain, _)| *domain)
+}
+
+fn referenced_class(typeid: &TypeRef, schema: &Schema) -> Option<String> {
+    match typeid {
+        TypeRef::Builtin(_) => None,
+        TypeRef::Vector(t) => referenced_class(t, schema),
+        TypeRef::Class(n) => Some(n.clone()),
+        TypeRef::Constructor(n) => schema.constructor(n).map(|t| t.result.clone()),
+    }
+}
+
+/// Where a class can go, given what uses it so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Place {
+    /// Nothing with a place uses it yet.
+    Free,
+    In(&'static str),
+    Common,
+}
+
+impl Place {
+    fn of(domain: Option<&'static str>) -> Place {
+        domain.map_or(Place::Common, Place::In)
+    }
+
+    /// Where something used from both places goes.
+    fn join(self, other: Place) -> Place {
+        match (self, other) {
+            (Place::Free, p) | (p, Place::Free) => p,
+            (Place::In(a), Place::In(b)) if a == b => Place::In(a),
+            _ => Place::Common,
+        }
+    }
+}
+
+/// Assigns classes, methods and updates to groups, leaving out those that
+/// everything needs.
+///
+/// Methods and updates go to the group their name is about. A class goes
+/// to the group of whatever uses it, if that is a single one, so enabling a
+/// group never requires enabling another one. Classes that nothing places
+/// go to the group of their name.
+pub fn assign(schema: &Schema) -> Domains {
+    let mut domains = Domains::default();
+    let mut places: HashMap<&str, Place> = HashMap::new();
+    let mut uses: Vec<(&str, Vec<String>)> = Vec::new();
+    for class in &schema.classes {
+        for t in &class.constructors {
+            let refs = schema
+                .constructor(t)
+                .unwrap()
+                .params
+                .iter()
+                .filter_map(|p| referenced_class(&p.typeid, schema))
+                .collect();
+            if class.name == UPDATES {
+                places.insert(t, Place::of(guess(t)));
+                uses.push((t, refs));
+            } else {
+                uses.push((&class.name, refs));
+            }
+        }
+        places.insert(&class.name, Place::Free);
+    }
+    places.insert(UPDATES, Place::Common);
+    for method in &schema.methods {
+        places.insert(&method.name, Place::of(guess(&method.name)));
+        let mut refs: Vec<String> = method
+            .params
+            .iter()
+            .filter_map(|p| referenced_class(&p.typeid, schema))
+            .collect();
+        refs.push(method.result.clone());
+        uses.push((&method.name, refs));
+    }
+    let mut placed = false;
+    loop {
+        let mut changed = false;
+        for (user, refs) in &uses {
+            let place = places[user];
+            for r in refs.iter().filter(|r| *r != user) {
+                let joined = places[r.as_str()].join(place);
+                if joined != places[r.as_str()] {
+                    places.insert(r, joined);
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            continue;
+        }
+        if placed {
+            break;
+        }
+        // What is left is only used by itself, if at all.
+        for class in &schema.classes {
+            if places[class.name.as_str()] == Place::Free {
+                places.insert(&class.name, Place::of(guess(&class.name)));
+            }
+        }
+        placed = true;
+    }
+    for (name, place) in places {
+        if let Place::In(domain) = place {
+            if schema.class(name).is_some() || schema.method(name).is_some() {
+                domains.items.insert(name.to_owned(), domain);
+            } else {
+                domains.updates.insert(name.to_owned(), domain);
+            }
+        }
+    }
+    domains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema::parse;
+
+    const SCHEMA: &str = "\
+//@description A photo
+photo id:int32 = Photo;
+
+//@description A sticker
+sticker photo:Photo = Sticker;
+
+//@description A message
+message text:string = Message;
+
+//@description A user
+user name:string = User;
+
+//@description An option
+option value:int32 = Option;
+
+//@description A chat nothing refers to
+lonelyChat id:int32 = LonelyChat;
+
+//@description A new message
+updateNewMessage message:message = Update;
+
+//@description An option changed
+updateOption value:Option = Update;
+
+---functions---
+
+//@description Returns a sticker
+getSticker id:int32 = Sticker;
+
+//@description Returns a message
+getMessage id:int32 = Message;
+
+//@description Returns the sender of a message
+getMessageSender id:int32 = User;
+
+//@description Returns a user
+getUser id:int32 = User;
+
+//@description Returns an option
+getOption name:string = Option;
+";
+
+    fn map(entries: &[(&str, &'static str)]) -> HashMap<String, &'static str> {
+        entries.iter().map(|&(name, domain)| (name.to_owned(), domain)).collect()
+    }
+
+    #[test]
+    fn groups() {
+        let domains = assign(&parse(SCHEMA).unwrap());
+        assert_eq!(
+            domains.items,
+            map(&[
+                // Only used by a sticker, which only a stickers method uses.
+                ("Photo", "stickers"),
+                ("Sticker", "stickers"),
+                ("getSticker", "stickers"),
+                // Used by a messages method and a messages update.
+                ("Message", "messages"),
+                ("getMessage", "messages"),
+                // `User` is used by both messages and users, so it's common.
+                ("getMessageSender", "messages"),
+                ("getUser", "users"),
+                // Used by nothing, so grouped by its name.
+                ("LonelyChat", "chats"),
+            ])
+        );
+        assert_eq!(domains.updates, map(&[("updateNewMessage", "messages")]));
+    }
+}
@@ -1,11 +1,117 @@
 extern crate pest;
+extern crate prettyplease;
 #[macro_use]
 extern crate pest_derive;
 #[macro_use]
 extern crate quote;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate syn;
+
+use std::collections::{HashMap, HashSet};
+
+pub mod diff;
+mod domain;
+pub mod schema;
+mod scc;
+
+pub use schema::{parse, ParseError, Schema};
+use schema::{Definition, Param, TypeRef};
+
+/// Classes that `polymorphic_classes` keeps as structs.
+const NOT_PROMOTED: &[&str] = &["Error", "Ok"];
+
+/// Options controlling the generated code.
+///
+/// `split_modules` and `domain_modules` only change where the code goes:
+/// the contents of the modules they add are re-exported, so paths to the
+/// types don't change.
+#[derive(Debug, Clone)]
+pub struct CodegenOptions {
+    /// Emit an enum for every class, even when it has a single constructor,
+    /// and mark all class enums `#[non_exhaustive]`.
+    ///
+    /// Fields and method responses then use the class enum, so `@type` is
+    /// checked on deserialization and code keeps compiling when TDLib adds
+    /// new constructors to a class. A constructor struct that would clash
+    /// with the name of its class gets a `Data` suffix, e.g. `ChatsData`.
+    ///
+    /// `Error` and `Ok` are left as structs: they are what every method can
+    /// answer with, and code handling errors builds and matches on them. An
+    /// enum of a single constructor implements `Default` if the constructor
+    /// does.
+    pub polymorphic_classes: bool,
+    /// Traits to derive in addition to `Serialize`, `Deserialize`, `Debug`,
+    /// `Clone` and `PartialEq`. Each one is only derived for the types that
+    /// can implement it.
+    pub derives: Vec<Derive>,
+    /// Put every class, with its constructors, in its own module named after
+    /// the class in snake_case, e.g. `types::chat_list`.
+    pub split_modules: bool,
+    /// Group classes and methods by the part of the API they belong to, e.g.
+    /// `types::passport` or `methods::payments`, each behind a cargo feature
+    /// of the same name. Anything needed by more than one group goes to an
+    /// always enabled `common` module. The variants of `Update` are grouped
+    /// one by one, and updates of a disabled group are read as `Unknown`.
+    pub domain_modules: bool,
+    /// Which of the types and methods to generate code for.
+    pub emit: Emit,
+    /// Box every field whose type is an object, not only those needed to
+    /// break recursive types. This keeps all types small, at the cost of an
+    /// allocation per field.
+    pub box_object_fields: bool,
+    /// Emit a builder for every method and every `input*` constructor, e.g.
+    /// `SendMessage::builder(chat_id, content).reply_to_message_id(id).build()`.
+    ///
+    /// The fields that must be set are the arguments of `builder`, while the
+    /// others have a setter and start out as `None`, `false`, empty or 0.
+    /// Objects are only optional if they derive `Default`, see `derives`.
+    pub builders: bool,
+    /// Format the generated code like rustfmt would, instead of putting it
+    /// all on a single line. This is meant for code that is read or checked
+    /// in, and can be turned off when the code is only compiled.
+    pub pretty: bool,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> CodegenOptions {
+        CodegenOptions {
+            polymorphic_classes: false,
+            derives: Vec::new(),
+            split_modules: false,
+            domain_modules: false,
+            emit: Emit::Both,
+            box_object_fields: false,
+            builders: false,
+            pretty: true,
+        }
+    }
+}
+
+/// Optional traits that can be derived on the generated types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Derive {
+    /// `Eq`, for types that contain no floating point number.
+    Eq,
+    /// `Hash`, for structs that contain no floating point number and no
+    /// class enum.
+    Hash,
+    /// `Default`, for structs whose fields all have a default value. The
+    /// other structs get a `new` function taking the fields that don't.
+    Default,
+    /// `schemars::JsonSchema`, for all types.
+    JsonSchema,
+}
 
-use pest::Parser;
-use std::collections::HashMap;
+/// What [`generate_with_options`] generates code for. The part that is not
+/// generated is returned as an empty string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Types,
+    Methods,
+    Both,
+}
 
 #[derive(Parser)]
 #[grammar = "tl.pest"]
@@ -18,6 +124,397 @@ fn capitalize(s: &str) -> String {
     v.into_iter().collect()
 }
 
+/// Names defined by the schema, collected before rendering so that
+/// documentation can link to items defined later in the file.
+#[derive(Debug)]
+struct Index {
+    types: HashMap<String, String>,
+    methods: HashSet<String>,
+    classes: HashMap<String, usize>,
+    options: CodegenOptions,
+    /// Names of the constructors, classes and methods that can derive each
+    /// of the optional traits.
+    derivable: HashMap<Derive, HashSet<String>>,
+    /// Constructor and field names of the fields that need a `Box`.
+    boxed: HashSet<(String, String)>,
+    /// Group of the classes, methods and updates put behind a cargo feature.
+    domains: domain::Domains,
+    /// Objects that builders don't require to be set.
+    unset_by_default: HashSet<String>,
+    /// Names of the classes and constructors that each constructor and
+    /// method refers to by the types of its fields, and each method by its
+    /// result.
+    references: HashMap<String, HashSet<String>>,
+}
+
+/// Adds the class or constructor that a type refers to, if any.
+fn add_reference(typeid: &TypeRef, names: &mut HashSet<String>) {
+    match typeid {
+        TypeRef::Builtin(_) => {}
+        TypeRef::Vector(t) => add_reference(t, names),
+        TypeRef::Class(n) | TypeRef::Constructor(n) => {
+            names.insert(n.clone());
+        }
+    }
+}
+
+impl Index {
+    fn new(schema: &Schema, options: &CodegenOptions) -> Index {
+        Index {
+            types: schema
+                .types
+                .iter()
+                .map(|t| (t.name.clone(), t.result.clone()))
+                .collect(),
+            methods: schema.methods.iter().map(|m| m.name.clone()).collect(),
+            classes: schema
+                .classes
+                .iter()
+                .map(|c| (c.name.clone(), c.constructors.len()))
+                .collect(),
+            options: options.clone(),
+            derivable: HashMap::new(),
+            boxed: HashSet::new(),
+            unset_by_default: HashSet::new(),
+            domains: if options.domain_modules {
+                domain::assign(schema)
+            } else {
+                domain::Domains::default()
+            },
+            references: schema
+                .types
+                .iter()
+                .map(|t| (&t.name, &t.params, None))
+                .chain(schema.methods.iter().map(|m| (&m.name, &m.params, Some(&m.result))))
+                .map(|(name, params, result)| {
+                    let mut names: HashSet<String> = result.cloned().into_iter().collect();
+                    for p in params {
+                        add_reference(&p.typeid, &mut names);
+                    }
+                    (name.clone(), names)
+                })
+                .collect(),
+        }
+    }
+
+    /// Finds the fields that make a type contain itself, directly or through
+    /// other types, and so must be boxed for it to have a size.
+    ///
+    /// Types that contain each other form the strongly connected components
+    /// of a graph from each constructor to the constructors its fields may
+    /// hold. A `Vec` already adds an indirection, while an `Option` doesn't.
+    /// Within a component only enough fields are boxed to break every cycle.
+    fn compute_boxed(&mut self, schema: &Schema) {
+        let mut edges: HashMap<String, Vec<String>> = HashMap::new();
+        let mut fields = Vec::new();
+        for t in &schema.types {
+            for p in &t.params {
+                let targets = match &p.typeid {
+                    TypeRef::Constructor(n) if self.class_enum(n).is_none() => vec![n.clone()],
+                    TypeRef::Constructor(n) | TypeRef::Class(n) => {
+                        schema.class(&capitalize(n)).unwrap().constructors.clone()
+                    }
+                    _ => continue,
+                };
+                edges.entry(t.name.clone()).or_default().extend(targets.iter().cloned());
+                fields.push((t.name.clone(), p.name.clone(), targets));
+            }
+        }
+        let cut = scc::cycle_edges(&edges);
+        self.boxed = fields
+            .into_iter()
+            .filter(|(owner, _, targets)| targets.iter().any(|t| cut.contains(&(owner.clone(), t.clone()))))
+            .map(|(owner, p, _)| (owner, p))
+            .collect();
+    }
+
+    /// The enum that a field typed as the given constructor holds, if any:
+    /// a constructor named like its class refers to the class.
+    fn class_enum(&self, constructor: &str) -> Option<String> {
+        let class = capitalize(constructor);
+        if self.is_enum(&class) {
+            Some(class)
+        } else {
+            None
+        }
+    }
+
+    fn is_enum(&self, class: &str) -> bool {
+        let constructors = self.classes.get(class).cloned().unwrap_or(0);
+        constructors > 1
+            || (self.options.polymorphic_classes && constructors > 0 && !NOT_PROMOTED.contains(&class))
+    }
+
+    /// The `cfg` attribute of a variant of a class enum, for the updates
+    /// that are grouped on their own.
+    fn variant_cfg(&self, constructor: &str) -> proc_macro2::TokenStream {
+        match self.domains.updates.get(constructor) {
+            Some(feature) => quote!{ #[cfg(feature = #feature)] },
+            None => quote!{},
+        }
+    }
+
+    /// Whether the class is an enum only because of `polymorphic_classes`.
+    /// Such an enum defaults to its one constructor, where that has a
+    /// default.
+    fn is_promoted(&self, class: &str) -> bool {
+        self.classes.get(class) == Some(&1) && self.is_enum(class)
+    }
+
+    fn struct_name(&self, constructor: &str) -> String {
+        let name = capitalize(constructor);
+        match self.types.get(constructor) {
+            Some(class) if self.options.polymorphic_classes && *class == name && self.is_enum(class) => {
+                format!("{}Data", name)
+            }
+            _ => name,
+        }
+    }
+
+    /// The feature of the group that a constructor, class or method is
+    /// in, unless it is always there.
+    fn group(&self, name: &str) -> Option<&'static str> {
+        let class = self.types.get(name).map_or(name, |class| class.as_str());
+        self.domains
+            .updates
+            .get(name)
+            .or_else(|| self.domains.items.get(class))
+            .cloned()
+    }
+
+    /// The rustdoc for a mention of a constructor, class or method in the
+    /// docs of `owner`. A link only where the target is there whenever the
+    /// owner is, since rustdoc warns about links to what is disabled.
+    fn link(&self, name: &str, owner: &str) -> Option<String> {
+        let (text, path) = if self.types.contains_key(name) {
+            (self.struct_name(name), None)
+        } else if self.classes.contains_key(name) {
+            (capitalize(name), None)
+        } else if self.methods.contains(name) {
+            if self.methods.contains(owner) {
+                (capitalize(name), None)
+            } else {
+                let depth = 1 + self.options.split_modules as usize + self.options.domain_modules as usize;
+                let path = format!("{}methods::{}", "super::".repeat(depth), capitalize(name));
+                (capitalize(name), Some(path))
+            }
+        } else {
+            return None;
+        };
+        let group = self.group(name);
+        Some(if group.is_some() && group != self.group(owner) {
+            format!("`{}`", text)
+        } else if let Some(path) = path {
+            format!("[`{}`]({})", text, path)
+        } else {
+            format!("[`{}`]", text)
+        })
+    }
+
+    /// Whether a field of the given type could derive `derive`, assuming
+    /// that the names in `ok` can.
+    fn field_derives(&self, typeid: &TypeRef, optional: bool, derive: Derive, ok: &HashSet<String>) -> bool {
+        if derive == Derive::Default && optional {
+            return true;
+        }
+        match typeid {
+            TypeRef::Builtin(t) => t != "double" || derive == Derive::Default,
+            TypeRef::Vector(t) => derive == Derive::Default || self.field_derives(t, false, derive, ok),
+            TypeRef::Constructor(t) => match self.class_enum(t) {
+                Some(class) => ok.contains(&class),
+                None => ok.contains(t),
+            },
+            TypeRef::Class(t) => ok.contains(t),
+        }
+    }
+
+    /// Finds out which types can derive `derive`, the way the compiler
+    /// would: `Eq` and `Hash` hold for recursive types unless something
+    /// breaks them, while a recursive `Default` needs an optional field to
+    /// stop at.
+    fn compute_derivable(&mut self, schema: &Schema, derive: Derive) {
+        let mut ok: HashSet<String> = HashSet::new();
+        if derive == Derive::JsonSchema {
+            ok.extend(self.types.keys().cloned());
+            ok.extend(self.classes.keys().cloned());
+        } else {
+            let greatest = derive != Derive::Default;
+            if greatest {
+                ok.extend(self.types.keys().cloned());
+                ok.extend(self.classes.keys().cloned());
+            }
+            loop {
+                let mut next = HashSet::new();
+                for t in &schema.types {
+                    if t.params.iter().all(|p| self.field_derives(&p.typeid, p.optional, derive, &ok)) {
+                        next.insert(t.name.clone());
+                    }
+                }
+                for c in &schema.classes {
+                    let derives = if self.is_promoted(&c.name) && derive == Derive::Default {
+                        next.contains(&c.constructors[0])
+                    } else if self.is_enum(&c.name) {
+                        // The `Unknown` variant holds a `serde_json::Value`.
+                        derive == Derive::Eq && c.constructors.iter().all(|t| next.contains(t))
+                    } else {
+                        next.contains(&c.constructors[0])
+                    };
+                    if derives {
+                        next.insert(c.name.clone());
+                    }
+                }
+                if next == ok {
+                    break;
+                }
+                ok = next;
+            }
+        }
+        for m in &schema.methods {
+            if m.params.iter().all(|p| self.field_derives(&p.typeid, p.optional, derive, &ok)) {
+                ok.insert(m.name.clone());
+            }
+        }
+        self.derivable.insert(derive, ok);
+    }
+
+    /// Whether a field of the given type implements `Default`.
+    fn has_default(&self, typeid: &TypeRef) -> bool {
+        let name = match typeid {
+            TypeRef::Class(n) | TypeRef::Constructor(n) => n,
+            _ => return true,
+        };
+        self.options.derives.contains(&Derive::Default)
+            && self.derivable[&Derive::Default].contains(name)
+            && (!self.is_enum(&capitalize(name)) || self.is_promoted(&capitalize(name)))
+    }
+
+    /// Whether a builder needs a value for the given field, assuming that the
+    /// objects in `unset` are fine to leave to their default value.
+    fn is_required(&self, param: &Param, unset: &HashSet<String>) -> bool {
+        if param.optional {
+            return false;
+        }
+        match &param.typeid {
+            TypeRef::Builtin(t) => t != "Bool" && !param.empty_allowed,
+            TypeRef::Vector(_) => false,
+            TypeRef::Class(n) | TypeRef::Constructor(n) => {
+                !self.has_default(&param.typeid) || !unset.contains(n)
+            }
+        }
+    }
+
+    /// Finds the objects with a default value that is fine to leave unset,
+    /// e.g. options that are all `false`, as opposed to a `FormattedText`
+    /// with no text.
+    fn compute_unset_by_default(&mut self, schema: &Schema) {
+        let mut unset = HashSet::new();
+        loop {
+            let mut next = HashSet::new();
+            for t in &schema.types {
+                if t.params.iter().all(|p| !self.is_required(p, &unset)) {
+                    next.insert(t.name.clone());
+                    if !self.is_enum(&t.result) || self.is_promoted(&t.result) {
+                        next.insert(t.result.clone());
+                    }
+                }
+            }
+            if next == unset {
+                break;
+            }
+            unset = next;
+        }
+        self.unset_by_default = unset;
+    }
+
+    /// Whether `Default` was asked for but can't be derived for the given
+    /// constructor or method.
+    fn lacks_default(&self, name: &str) -> bool {
+        self.options.derives.contains(&Derive::Default) && !self.derivable[&Derive::Default].contains(name)
+    }
+
+    /// The optional derives that apply to the given constructor, class or
+    /// method. `Default` is implemented by hand for enums.
+    fn derives(&self, name: &str) -> proc_macro2::TokenStream {
+        let derives = self
+            .options
+            .derives
+            .iter()
+            .filter(|d| self.derivable[d].contains(name))
+            .filter(|d| **d != Derive::Default || !self.is_enum(name))
+            .map(|d| match d {
+                Derive::Eq => quote!{ Eq },
+                Derive::Hash => quote!{ Hash },
+                Derive::Default => quote!{ Default },
+                Derive::JsonSchema => quote!{ ::schemars::JsonSchema },
+            });
+        quote!{ #(, #derives)* }
+    }
+}
+
+/// Turns a class name into a module name, e.g. `ChatList` into `chat_list`.
+fn snake_case(s: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in s.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    match out.as_str() {
+        "as" | "break" | "const" | "continue" | "crate" | "else" | "enum" | "extern" | "false"
+        | "fn" | "for" | "if" | "impl" | "in" | "let" | "loop" | "match" | "mod" | "move"
+        | "mut" | "pub" | "ref" | "return" | "self" | "static" | "struct" | "super" | "trait"
+        | "true" | "type" | "unsafe" | "use" | "where" | "while" => out.push('_'),
+        _ => {}
+    }
+    out
+}
+
+/// Splits documentation into the lines of a `///` comment, so that it is
+/// printed as one.
+fn doc_lines(doc: &str) -> Vec<String> {
+    doc.lines()
+        .map(|l| if l.is_empty() { String::new() } else { format!(" {}", l) })
+        .collect()
+}
+
+/// Turns a tl doc string of `owner` into rustdoc, linking the names of the
+/// types that `owner` refers to.
+fn render_doc(doc: &str, index: &Index, owner: &str) -> String {
+    let references = index.references.get(owner);
+    doc.split(' ')
+        .map(|word| {
+            let start = word.find(char::is_alphanumeric);
+            let end = word.rfind(char::is_alphanumeric);
+            let (start, end) = match (start, end) {
+                (Some(s), Some(e)) => (s, e + 1),
+                _ => return word.to_owned(),
+            };
+            let mut pre = &word[..start];
+            let mut post = &word[end..];
+            let name = &word[start..end];
+            if !references.is_some_and(|r| r.contains(name)) {
+                return word.to_owned();
+            }
+            match index.link(name, owner) {
+                Some(link) => {
+                    if pre.ends_with('`') && post.starts_with('`') {
+                        pre = &pre[..pre.len() - 1];
+                        post = &post[1..];
+                    }
+                    format!("{}{}{}", pre, link, post)
+                }
+                None => word.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 fn convert_type(t: &str) -> proc_macro2::Ident {
     format_ident!("{}", match t {
         "double" => "f64".to_owned(),
@@ -31,43 +528,37 @@ fn convert_type(t: &str) -> proc_macro2::Ident {
     })
 }
 
-fn convert_typeid(pair: pest::iterators::Pair<Rule>) -> proc_macro2::TokenStream {
-    match pair.as_rule() {
-        Rule::vector => {
-            let inner = pair
-                .into_inner()
-                .next()
-                .unwrap()
-                .into_inner()
-                .next()
-                .unwrap();
+fn convert_typeid(typeid: &TypeRef) -> proc_macro2::TokenStream {
+    match typeid {
+        TypeRef::Vector(inner) => {
             let t = convert_typeid(inner);
             quote! {Vec<#t>}
         }
-        Rule::ident => {
-            let ident = pair.as_str();
+        TypeRef::Builtin(ident) | TypeRef::Class(ident) | TypeRef::Constructor(ident) => {
             let t = convert_type(ident);
             quote! { #t }
         }
-        _ => unreachable!(),
     }
 }
 
+/// Renders a field of the constructor or method `owner`.
 fn render_param(
-    pair: pest::iterators::Pair<Rule>,
-    docinfo: &HashMap<String, ParamDocInfo>,
-    parent_class: &str,
-) -> proc_macro2::TokenStream {
-    let mut pairs = pair.into_inner();
-    let mut name = pairs.next().unwrap().as_str().to_owned();
-    let docinfo = docinfo.get(&name).unwrap();
-    let typeid = pairs.next().unwrap();
-    let typeid = typeid.into_inner().next().unwrap();
-    let mut typeid = convert_typeid(typeid);
+    param: &Param,
+    owner: &str,
+    index: &Index,
+) -> (proc_macro2::TokenStream, Field) {
+    let mut name = param.name.clone();
+    let plain = convert_typeid(&param.typeid);
+    let mut typeid = plain.clone();
     let typeid_str = format!("{}", typeid);
-    if typeid_str == parent_class {
+    let is_object = matches!(param.typeid, TypeRef::Class(_) | TypeRef::Constructor(_));
+    let recursive = index.boxed.contains(&(owner.to_owned(), param.name.clone()));
+    let boxed = recursive || (index.options.box_object_fields && is_object);
+    if boxed {
         typeid = quote!{ Box<#typeid> };
     }
+    let defaultable = param.optional || index.has_default(&param.typeid);
+    let required = index.is_required(param, &index.unset_by_default);
     let mut pre = if name == "type" {
         name.push('_');
         quote! {
@@ -84,7 +575,7 @@ fn render_param(
         quote! {}
     };
     let serialize_number = if typeid_str == "i32" || typeid_str == "i64" {
-        if docinfo.optional {
+        if param.optional {
             quote!{
                 #[serde(deserialize_with="deserialize_opt_i32")]
             }
@@ -96,209 +587,690 @@ fn render_param(
     } else {
         quote! {}
     };
-    let typeid = if docinfo.optional {
+    let typeid = if param.optional {
         quote!{Option<#typeid>}
     } else {
         quote!{#typeid}
     };
     let name = format_ident!("{}", name);
-    let doc = docinfo.doc.replace("//-", " ");
+    let doc = render_doc(&param.doc, index, owner);
+    let doc_lines = doc_lines(&doc);
     pre.extend(quote! {
-        #[doc = #doc]
+        #(#[doc = #doc_lines])*
         #serialize_number
         #default_false
         pub #name:#typeid
     });
-    pre
+    let field = Field {
+        name,
+        typeid,
+        doc,
+        plain,
+        boxed,
+        optional: param.optional,
+        defaultable,
+        required,
+    };
+    (pre, field)
 }
 
 #[derive(Debug)]
-struct Class {
-    name: String,
-    types: Vec<String>,
+struct Field {
+    name: proc_macro2::Ident,
+    typeid: proc_macro2::TokenStream,
     doc: String,
+    /// The type without `Option` and `Box`.
+    plain: proc_macro2::TokenStream,
+    boxed: bool,
+    optional: bool,
+    /// Whether the type of the field implements `Default`.
+    defaultable: bool,
+    /// Whether a builder needs a value for the field.
+    required: bool,
+}
+
+/// The value of a field from an argument named like it, which takes any
+/// type that converts into the field's one.
+fn field_value(field: &Field) -> proc_macro2::TokenStream {
+    let field_name = &field.name;
+    let mut value = quote!{ #field_name.into() };
+    if field.boxed {
+        value = quote!{ Box::new(#value) };
+    }
+    if field.optional {
+        value = quote!{ Some(#value) };
+    }
+    value
+}
+
+/// Renders the arguments for the fields selected by `is_arg`, and a struct
+/// expression setting them while leaving the others to their default.
+fn init_from_args<F: Fn(&Field) -> bool>(
+    name: &proc_macro2::Ident,
+    fields: &[Field],
+    is_arg: F,
+) -> (Vec<proc_macro2::TokenStream>, proc_macro2::TokenStream) {
+    let args = fields
+        .iter()
+        .filter(|f| is_arg(f))
+        .map(|f| {
+            let field_name = &f.name;
+            let plain = &f.plain;
+            quote!{ #field_name: impl Into<#plain> }
+        })
+        .collect();
+    let inits = fields.iter().map(|f| {
+        let field_name = &f.name;
+        if is_arg(f) {
+            let value = field_value(f);
+            quote!{ #field_name: #value }
+        } else {
+            quote!{ #field_name: Default::default() }
+        }
+    });
+    (args, quote!{ #name { #(#inits),* } })
+}
+
+/// Renders `X::new` for a struct that can't derive `Default`, taking the
+/// fields that have no default value. Together with struct update syntax
+/// it replaces `Default`, e.g. `X { a: 1, ..X::new(b) }`.
+fn render_new(name: &proc_macro2::Ident, fields: &[Field]) -> proc_macro2::TokenStream {
+    if fields.iter().all(|f| !f.defaultable) {
+        return quote!{};
+    }
+    let (args, init) = init_from_args(name, fields, |f| !f.defaultable);
+    let doc = format!(
+        " Creates a [`{}`] from the fields that have no default value, setting the others to their default.",
+        name
+    );
+    quote! {
+        impl #name {
+            #[doc = #doc]
+            #[allow(clippy::too_many_arguments)]
+            pub fn new(#(#args),*) -> #name {
+                #init
+            }
+        }
+    }
+}
+
+/// Renders `X::builder`, taking the required fields of `X`, and a builder
+/// type with a setter for each of the other fields.
+fn render_builder(name: &proc_macro2::Ident, fields: &[Field]) -> proc_macro2::TokenStream {
+    let builder = format_ident!("{}Builder", name);
+    let (args, init) = init_from_args(name, fields, |f| f.required);
+    let setters = fields.iter().filter(|f| !f.required).map(|f| {
+        let field_name = &f.name;
+        let plain = &f.plain;
+        let doc = doc_lines(&f.doc);
+        let value = field_value(f);
+        quote! {
+            #(#[doc = #doc])*
+            pub fn #field_name(mut self, #field_name: impl Into<#plain>) -> Self {
+                self.inner.#field_name = #value;
+                self
+            }
+        }
+    });
+    let builder_doc = format!(" Starts building a [`{}`] from the fields that must be set.", name);
+    let struct_doc = format!(" Builder for [`{0}`], see [`{0}::builder`].", name);
+    quote! {
+        impl #name {
+            #[doc = #builder_doc]
+            #[allow(clippy::too_many_arguments)]
+            pub fn builder(#(#args),*) -> #builder {
+                #builder {
+                    inner: #init,
+                }
+            }
+        }
+        #[doc = #struct_doc]
+        #[derive(Debug, Clone)]
+        pub struct #builder {
+            inner: #name,
+        }
+        impl #builder {
+            #(#setters)*
+            pub fn build(self) -> #name {
+                self.inner
+            }
+        }
+    }
+}
+
+/// Fields that every constructor of a class has, with the same type.
+fn common_fields<'a>(variants: &[&'a [Field]]) -> Vec<&'a Field> {
+    let (first, rest) = match variants.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    first
+        .iter()
+        .filter(|f| {
+            rest.iter().all(|fields| {
+                fields.iter().any(|g| {
+                    g.name == f.name && g.typeid.to_string() == f.typeid.to_string()
+                })
+            })
+        })
+        .collect()
 }
 
 fn render_type(
-    pair: pest::iterators::Pair<Rule>,
-    docinfo: TypeDocInfo,
-    classes: &mut HashMap<String, Class>,
+    definition: &Definition,
+    fields: &mut HashMap<String, Vec<Field>>,
+    index: &Index,
 ) -> proc_macro2::TokenStream {
-    let mut pairs = pair.into_inner();
-    let name = pairs.next().unwrap().as_str();
-    let name_capitalized = format_ident!("{}", capitalize(name));
-    let params = pairs.next().unwrap();
-    let classname = capitalize(pairs.next().unwrap().as_str());
-    let params = params
-        .into_inner()
-        .map(|p| render_param(p, &docinfo.params, &classname))
-        .collect::<Vec<_>>();
-    let class = classes.entry(classname.clone()).or_insert_with(|| Class {
-        name: classname,
-        types: Vec::new(),
-        doc: String::new(),
-    });
-    class.types.push(capitalize(name));
+    let name = &definition.name;
+    let name_capitalized = format_ident!("{}", index.struct_name(name));
+    let classname = capitalize(&definition.result);
+    let (params, type_fields): (Vec<_>, Vec<_>) = definition
+        .params
+        .iter()
+        .map(|p| render_param(p, name, index))
+        .unzip();
+    let mut doc = render_doc(&definition.doc, index, name);
+    doc.push_str(&format!("\n\nTL constructor `{}`", name));
+    if index.is_enum(&definition.result) {
+        doc.push_str(&format!(" of class [`{}`]", classname));
+    }
+    doc.push('.');
+    let mut builder = if index.options.builders && name.starts_with("input") {
+        render_builder(&name_capitalized, &type_fields)
+    } else {
+        quote!{}
+    };
+    if index.lacks_default(name) {
+        builder.extend(render_new(&name_capitalized, &type_fields));
+    }
+    fields.insert(name.clone(), type_fields);
+    let doc = doc_lines(&doc);
+    let derives = index.derives(name);
 
-    let doc = docinfo.doc.replace("//-", " ");
     quote! {
-        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-        #[doc = #doc]
+        #(#[doc = #doc])*
+        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq #derives)]
         pub struct #name_capitalized {
             #(#params),*
         }
+        #builder
     }
 }
 
-fn render_method(pair: pest::iterators::Pair<Rule>, docinfo: TypeDocInfo) -> proc_macro2::TokenStream {
-    let mut pairs = pair.into_inner();
-    let name = pairs.next().unwrap().as_str();
+fn render_method(definition: &Definition, index: &Index) -> proc_macro2::TokenStream {
+    let name = &definition.name;
     let name_capitalized = capitalize(name);
-    let params = pairs.next().unwrap();
-    let params = params
-        .into_inner()
-        .map(|p| render_param(p, &docinfo.params, ""))
-        .collect::<Vec<_>>();
+    let (params, fields): (Vec<_>, Vec<_>) = definition
+        .params
+        .iter()
+        .map(|p| render_param(p, name, index))
+        .unzip();
     let name_ident = format_ident!("{}",name_capitalized);
-    let rettype = convert_type(pairs.next().unwrap().as_str());
+    let mut builder = if index.options.builders {
+        render_builder(&name_ident, &fields)
+    } else {
+        quote!{}
+    };
+    if index.lacks_default(name) {
+        builder.extend(render_new(&name_ident, &fields));
+    }
+    let rettype = convert_type(&definition.result);
+    // `ok` carries no data, and an `error` is how TDLib reports a failure,
+    // so it can't be told apart from a successful response to a method
+    // returning `Error` but by reading it as one.
+    let (response, read_response) = match definition.result.as_str() {
+        "Ok" => (quote!{ () }, quote!{}),
+        "Error" => (
+            quote!{ #rettype },
+            quote! {
+                fn read_response(_type: &str, json: &str) -> MethodResult<Self> {
+                    Self::parse_response(json)
+                }
+            },
+        ),
+        _ => (quote!{ #rettype }, quote!{}),
+    };
 
-    let doc = docinfo.doc.replace("//-", " ");
+    let mut doc = render_doc(&definition.doc, index, name);
+    if definition.result == "Ok" {
+        doc.push_str(&format!("\n\nTL function `{}`, returns `ok`, read as `()`.", name));
+    } else {
+        doc.push_str(&format!("\n\nTL function `{}`, returns [`{}`].", name, rettype));
+    }
+    let doc = doc_lines(&doc);
+    let derives = index.derives(name);
     quote! {
-        #[derive(Serialize, Deserialize, Debug, Clone)]
-        #[doc = #doc]
+        #(#[doc = #doc])*
+        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq #derives)]
         pub struct #name_ident {
             #(#params),*
         }
         impl Method for #name_ident {
             const TYPE: &'static str = #name;
-            type Response = #rettype;
+            type Response = #response;
+            #read_response
         }
+        #builder
     }
 }
 
-fn render_class(class: Class) -> proc_macro2::TokenStream {
+fn render_class(
+    class: &schema::Class,
+    fields: &HashMap<String, Vec<Field>>,
+    index: &Index,
+) -> proc_macro2::TokenStream {
+    if !index.is_enum(&class.name) {
+        return quote!{};
+    }
     let name = format_ident!("{}",class.name);
     let types = class
-        .types
-        .into_iter()
-        .map(|t| format_ident!("{}",t))
+        .constructors
+        .iter()
+        .map(|t| format_ident!("{}",capitalize(t)))
         .collect::<Vec<_>>();
-    if types.len() <= 1 {
-        return quote!{};
+    let structs = class
+        .constructors
+        .iter()
+        .map(|t| format_ident!("{}",index.struct_name(t)))
+        .collect::<Vec<_>>();
+    let cfgs = class
+        .constructors
+        .iter()
+        .map(|t| index.variant_cfg(t))
+        .collect::<Vec<_>>();
+    let mut doc = render_doc(&class.doc, index, &class.name);
+    doc.push_str(&format!("\n\nTL class `{}`, one of:\n", class.name));
+    for t in &class.constructors {
+        doc.push_str(&format!("\n* {}", index.link(t, &class.name).unwrap()));
     }
-    let types2 = types.clone();
-    let doc = class.doc.replace("//-", " ");
+    doc.push_str("\n\nObjects with a `@type` unknown to these bindings are kept as `Unknown`.");
+    let doc = doc_lines(&doc);
+    let tl_names = &class.constructors;
+    let non_exhaustive = if index.options.polymorphic_classes {
+        quote!{ #[non_exhaustive] }
+    } else {
+        quote!{}
+    };
+    let derives = index.derives(&class.name);
+    let default = if index.is_promoted(&class.name)
+        && index.options.derives.contains(&Derive::Default)
+        && index.derivable[&Derive::Default].contains(&class.name)
+    {
+        let t = &types[0];
+        quote! {
+            impl Default for #name {
+                fn default() -> #name {
+                    #name::#t(Default::default())
+                }
+            }
+        }
+    } else {
+        quote!{}
+    };
+    let (schema_attrs, schema_skip) = if index.options.derives.contains(&Derive::JsonSchema) {
+        (
+            quote!{ #[schemars(tag="@type", rename_all="camelCase")] },
+            quote!{ #[schemars(skip)] },
+        )
+    } else {
+        (quote!{}, quote!{})
+    };
+    let variants = class
+        .constructors
+        .iter()
+        .map(|t| fields[t].as_slice())
+        .collect::<Vec<_>>();
+    let accessors = common_fields(&variants).into_iter().map(|field| {
+        let field_name = &field.name;
+        let plain = &field.plain;
+        let copy = ["bool", "i32", "i64", "f64"].contains(&plain.to_string().as_str());
+        // `None` for an unknown constructor, as well as for an unset field.
+        let (typeid, value) = match (copy, field.optional, field.boxed) {
+            (true, true, _) => (quote!{ #plain }, quote!{ v.#field_name }),
+            (true, false, _) => (quote!{ #plain }, quote!{ Some(v.#field_name) }),
+            (false, true, true) => (quote!{ &#plain }, quote!{ v.#field_name.as_deref() }),
+            (false, true, false) => (quote!{ &#plain }, quote!{ v.#field_name.as_ref() }),
+            (false, false, true) => (quote!{ &#plain }, quote!{ Some(&*v.#field_name) }),
+            (false, false, false) => (quote!{ &#plain }, quote!{ Some(&v.#field_name) }),
+        };
+        let doc = doc_lines(&field.doc);
+        quote! {
+            #(#[doc = #doc])*
+            pub fn #field_name(&self) -> Option<#typeid> {
+                match self {
+                    #(#cfgs #name::#types(v) => #value,)*
+                    #name::Unknown { .. } => None,
+                }
+            }
+        }
+    });
+    let conversions = types.iter().zip(&structs).zip(&cfgs).map(|((t, s), cfg)| {
+        quote! {
+            #cfg
+            impl From<#s> for #name {
+                fn from(v: #s) -> #name {
+                    #name::#t(v)
+                }
+            }
+            #cfg
+            impl ::std::convert::TryFrom<#name> for #s {
+                type Error = #name;
+                fn try_from(v: #name) -> Result<#s, #name> {
+                    match v {
+                        #name::#t(v) => Ok(v),
+                        v => Err(v),
+                    }
+                }
+            }
+        }
+    });
     quote! {
-        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-        #[serde(rename_all="camelCase")]
-        #[serde(tag="@type")]
-        #[doc = #doc]
+        #(#[doc = #doc])*
+        #[derive(Debug, Clone, PartialEq #derives)]
+        #schema_attrs
+        #non_exhaustive
         pub enum #name {
-            #(#types(#types2)),*
+            #(#cfgs #types(#structs),)*
+            /// An object whose `@type` is not part of the schema these
+            /// bindings were generated from.
+            #schema_skip
+            Unknown {
+                type_: String,
+                raw: ::serde_json::Value,
+            },
         }
+        impl ::serde::Serialize for #name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                #[derive(Serialize)]
+                #[serde(rename_all="camelCase")]
+                #[serde(tag="@type")]
+                #[allow(clippy::enum_variant_names)]
+                enum Tagged<'a> {
+                    #(#cfgs #types(&'a #structs)),*
+                }
+                match self {
+                    #(#cfgs #name::#types(v) => ::serde::Serialize::serialize(&Tagged::#types(v), serializer),)*
+                    #name::Unknown { raw, .. } => ::serde::Serialize::serialize(raw, serializer),
+                }
+            }
+        }
+        impl<'de> ::serde::Deserialize<'de> for #name {
+            fn deserialize<D>(deserializer: D) -> Result<#name, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                deserializer.deserialize_map(ClassVisitor(::std::marker::PhantomData))
+            }
+        }
+        impl ClassEnum for #name {
+            fn from_map<'de, A>(type_: String, map: A) -> Result<#name, A::Error>
+            where
+                A: ::serde::de::MapAccess<'de>,
+            {
+                let rest = ::serde::de::value::MapAccessDeserializer::new(map);
+                match type_.as_str() {
+                    #(#cfgs #tl_names => ::serde::Deserialize::deserialize(rest).map(#name::#types),)*
+                    _ => {
+                        let mut raw: ::serde_json::Map<String, ::serde_json::Value> = ::serde::Deserialize::deserialize(rest)?;
+                        raw.insert("@type".to_owned(), type_.clone().into());
+                        Ok(#name::Unknown { type_, raw: raw.into() })
+                    }
+                }
+            }
+            fn from_value(type_: String, raw: ::serde_json::Value) -> Result<#name, ::serde_json::Error> {
+                match type_.as_str() {
+                    #(#cfgs #tl_names => ::serde_json::from_value(raw).map(#name::#types),)*
+                    _ => Ok(#name::Unknown { type_, raw }),
+                }
+            }
+        }
+        impl #name {
+            #(#accessors)*
+        }
+        #default
+        #(#conversions)*
     }
 }
 
-#[derive(Debug)]
-struct ParamDocInfo {
-    optional: bool,
-    doc: String,
-}
-#[derive(Debug)]
-struct TypeDocInfo {
-    doc: String,
-    params: HashMap<String, ParamDocInfo>,
+/// Generates the types and the methods defined in a tl file, as two
+/// separate pieces of code.
+///
+/// The code expects to be included in two sibling modules, `types` and
+/// `methods`, with the `methods` one importing everything from `types`. It
+/// refers to the `serde`, `serde_json` and `serde_aux` crates by absolute
+/// path and needs the serde derive macros, and in `methods` a `Method`
+/// trait and its `MethodResult` alias, to be in scope.
+///
+/// Methods returning `ok` have `()` as their `Response`. Those returning
+/// `Error` define `Method::read_response` to read any answer as a response,
+/// calling `Method::parse_response`.
+///
+/// The output only depends on the tl file and the options, so it can be
+/// checked in and diffed across schema versions.
+pub fn generate(src: &str) -> (String, String) {
+    generate_with_options(src, &CodegenOptions::default())
 }
 
-fn extract_docinfo(
-    pair: pest::iterators::Pair<Rule>,
-    classes: &mut HashMap<String, Class>,
-) -> TypeDocInfo {
-    let mut params = HashMap::new();
-    let mut doc = String::new();
-    for p in pair.into_inner() {
-        let mut pairs = p.into_inner();
-        let name = pairs.next().unwrap().as_str();
-        let descr = pairs.next().unwrap().as_str();
-        if name == "description" {
-            doc = descr.to_owned();
-        } else if name == "class" {
-            classes.insert(
-                name.to_owned(),
-                Class {
-                    name: name.to_owned(),
-                    types: Vec::new(),
-                    doc: descr.to_owned(),
-                },
-            );
-        } else {
-            let optional = descr.contains("may be null")
-                || descr.contains("only available to bots")
-                || descr.contains("bots only")
-                || descr.contains("or null");
-            let n = if name == "param_description" {
-                "description"
-            } else {
-                name
-            };
-            params.insert(
-                n.to_owned(),
-                ParamDocInfo {
-                    optional,
-                    doc: descr.to_owned(),
-                },
-            );
-        }
+pub fn generate_with_options(src: &str, options: &CodegenOptions) -> (String, String) {
+    let schema = parse(src).unwrap_or_else(|e| panic!("{}", e));
+    let mut index = Index::new(&schema, options);
+    index.compute_boxed(&schema);
+    for &derive in &options.derives {
+        index.compute_derivable(&schema, derive);
     }
-    TypeDocInfo { doc, params }
-}
-
-pub fn generate(src: &str) -> (String, String) {
-    let pairs = TlParser::parse(Rule::tl, &src).unwrap_or_else(|e| panic!("{}", e));
+    index.compute_unset_by_default(&schema);
 
-    let mut functions = false;
-    let mut classes = HashMap::new();
+    let mut fields = HashMap::new();
     let mut type_tokens = quote!{
-        use serde::de::Deserializer;
-        use serde::de::Deserialize;
         #[derive(Debug, Deserialize)]
         struct WrappedI32(#[serde(deserialize_with="::serde_aux::field_attributes::deserialize_number_from_string")] i32);
         fn deserialize_opt_i32<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
         where
-            D: Deserializer<'de>,
+            D: ::serde::Deserializer<'de>,
         {
-            Option::<WrappedI32>::deserialize(deserializer)
+            <Option<WrappedI32> as ::serde::Deserialize>::deserialize(deserializer)
                 .map(|opt_wrapped: Option<WrappedI32>| {
                     opt_wrapped.map(|wrapped: WrappedI32| wrapped.0)
                 })
         }
-    };
-    let mut method_tokens = quote!{};
-    for pair in pairs {
-        match pair.as_rule() {
-            Rule::section => {
-                functions = true;
+        /// A class enum, read by the `@type` of its objects.
+        trait ClassEnum: Sized {
+            /// Reads the rest of an object whose `@type` came first.
+            fn from_map<'de, A>(type_: String, map: A) -> Result<Self, A::Error>
+            where
+                A: ::serde::de::MapAccess<'de>;
+            /// Reads a whole object.
+            fn from_value(type_: String, raw: ::serde_json::Value) -> Result<Self, ::serde_json::Error>;
+        }
+        /// Reads a class enum. TDLib puts the `@type` of an object first, so
+        /// that the rest can be read right into the variant; an object with
+        /// its `@type` elsewhere is collected first, as is the rest of one of
+        /// an unknown type.
+        struct ClassVisitor<T>(::std::marker::PhantomData<T>);
+        impl<'de, T: ClassEnum> ::serde::de::Visitor<'de> for ClassVisitor<T> {
+            type Value = T;
+            fn expecting(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.write_str("an object with a `@type`")
             }
-            Rule::definition => {
-                let mut pairs = pair.into_inner();
-                let docstring = pairs.next().unwrap();
-                let typedef = pairs.next().unwrap();
-                let docinfo = extract_docinfo(docstring, &mut classes);
-                if functions {
-                    method_tokens.extend(render_method(typedef, docinfo));
-                } else {
-                    type_tokens.extend(render_type(typedef, docinfo, &mut classes));
+            fn visit_map<A>(self, mut map: A) -> Result<T, A::Error>
+            where
+                A: ::serde::de::MapAccess<'de>,
+            {
+                let missing = || <A::Error as ::serde::de::Error>::missing_field("@type");
+                let key: String = map.next_key()?.ok_or_else(missing)?;
+                if key == "@type" {
+                    let type_ = map.next_value()?;
+                    return T::from_map(type_, map);
                 }
+                let mut raw = ::serde_json::Map::new();
+                raw.insert(key, map.next_value()?);
+                while let Some((key, value)) = map.next_entry()? {
+                    raw.insert(key, value);
+                }
+                let type_ = match raw.get("@type").and_then(|t| t.as_str()) {
+                    Some(t) => t.to_owned(),
+                    None => return Err(missing()),
+                };
+                T::from_value(type_, raw.into()).map_err(<A::Error as ::serde::de::Error>::custom)
             }
-            Rule::EOI => {
+        }
+    };
+    let mut method_tokens = quote!{};
+    let mut type_groups: HashMap<&str, proc_macro2::TokenStream> = HashMap::new();
+    let mut method_groups: HashMap<&str, proc_macro2::TokenStream> = HashMap::new();
+    for class in &schema.classes {
+        let mut tokens = quote!{};
+        for constructor in &class.constructors {
+            let definition = schema.constructor(constructor).unwrap();
+            let rendered = render_type(definition, &mut fields, &index);
+            match index.domains.updates.get(constructor) {
+                Some(group) => type_groups.entry(group).or_default().extend(rendered),
+                None => tokens.extend(rendered),
             }
-            _ => {
-                unreachable!();
+        }
+        tokens.extend(render_class(class, &fields, &index));
+        if options.split_modules {
+            tokens = module(&snake_case(&class.name), None, tokens);
+        }
+        let group = index.domains.items.get(&class.name).cloned().unwrap_or("common");
+        type_groups.entry(group).or_default().extend(tokens);
+    }
+    for definition in &schema.methods {
+        let group = index.domains.items.get(&definition.name).cloned().unwrap_or("common");
+        method_groups
+            .entry(group)
+            .or_default()
+            .extend(render_method(definition, &index));
+    }
+    if options.domain_modules {
+        let features = domain::DOMAINS.iter().map(|(name, _)| *name);
+        for group in Some("common").into_iter().chain(features) {
+            let feature = if group == "common" { None } else { Some(group) };
+            if let Some(tokens) = type_groups.remove(group) {
+                type_tokens.extend(module(group, feature, tokens));
+            }
+            if let Some(tokens) = method_groups.remove(group) {
+                method_tokens.extend(module(group, feature, tokens));
             }
         }
+    } else {
+        type_tokens.extend(type_groups.remove("common"));
+        method_tokens.extend(method_groups.remove("common"));
+    }
+    match options.emit {
+        Emit::Types => method_tokens = quote!{},
+        Emit::Methods => type_tokens = quote!{},
+        Emit::Both => {}
+    }
+    (render_file(type_tokens, options), render_file(method_tokens, options))
+}
+
+/// Wraps code in a module whose contents are re-exported by its parent,
+/// optionally behind a cargo feature.
+fn module(name: &str, feature: Option<&str>, tokens: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let name = format_ident!("{}", name);
+    let cfg = match feature {
+        Some(feature) => quote!{ #[cfg(feature = #feature)] },
+        None => quote!{},
+    };
+    quote! {
+        #cfg
+        pub mod #name {
+            #[allow(unused_imports)]
+            use super::*;
+            #tokens
+        }
+        #cfg
+        pub use self::#name::*;
+    }
+}
+
+/// Header of every generated file.
+const HEADER: &str = "// This file is generated by tl-codegen. Do not edit.\n\n";
+
+fn render_file(tokens: proc_macro2::TokenStream, options: &CodegenOptions) -> String {
+    if tokens.is_empty() {
+        return String::new();
+    }
+    let code = if options.pretty {
+        let file = syn::parse2(tokens).unwrap_or_else(|e| panic!("generated invalid code: {}", e));
+        prettyplease::unparse(&file)
+    } else {
+        format!("{}\n", tokens)
+    };
+    format!("{}{}", HEADER, code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxed(src: &str) -> Vec<(String, String)> {
+        let schema = parse(src).unwrap();
+        let mut index = Index::new(&schema, &CodegenOptions::default());
+        index.compute_boxed(&schema);
+        let mut boxed: Vec<_> = index.boxed.into_iter().collect();
+        boxed.sort();
+        boxed
     }
-    for (_, class) in classes.into_iter() {
-        type_tokens.extend(render_class(class));
+
+    #[test]
+    fn vec_breaks_cycle() {
+        let src = "\
+//@description A tree @children The children of the node
+tree children:vector<tree> = Tree;
+";
+        assert!(boxed(src).is_empty());
+    }
+
+    #[test]
+    fn option_cycle() {
+        let src = "\
+//@description A linked list @value The value @next The next node; may be null
+node value:int32 next:node = Node;
+";
+        let schema = parse(src).unwrap();
+        assert!(schema.types[0].params[1].optional);
+        assert_eq!(boxed(src), vec![("node".to_owned(), "next".to_owned())]);
+    }
+
+    #[test]
+    fn vendored_schema() {
+        let boxed = boxed(include_str!("../../tdlib-types/td_api.tl"));
+        let expected = [
+            ("pageBlockCover", "cover"),
+            ("richTextAnchorLink", "text"),
+            ("richTextBold", "text"),
+            ("richTextEmailAddress", "text"),
+            ("richTextFixed", "text"),
+            ("richTextItalic", "text"),
+            ("richTextMarked", "text"),
+            ("richTextPhoneNumber", "text"),
+            ("richTextReference", "reference_text"),
+            ("richTextReference", "text"),
+            ("richTextStrikethrough", "text"),
+            ("richTextSubscript", "text"),
+            ("richTextSuperscript", "text"),
+            ("richTextUnderline", "text"),
+            ("richTextUrl", "text"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(owner, field)| (owner.to_owned(), field.to_owned()))
+            .collect();
+        assert_eq!(boxed, expected);
+    }
+
+    #[test]
+    fn links_type_references() {
+        let (types, methods) = generate(include_str!("../../tdlib-types/td_api.tl"));
+        let get_chat = &methods[methods.find("pub struct GetChat ").unwrap()..];
+        let get_chat = &get_chat[..get_chat.find('}').unwrap()];
+        assert!(get_chat.contains("/// [`Chat`] identifier"), "{}", get_chat);
+        // Only what a field or the result refers to is linked.
+        assert!(types.contains("/// Message identifier, unique for the chat to which the message belongs"));
     }
-    (format!("{}",type_tokens), format!("{}",method_tokens))
 }
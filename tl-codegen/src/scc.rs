@@ -0,0 +1,163 @@
+//! Strongly connected components of the graph of types containing each
+//! other, used to find out which fields need to be boxed.
+
+use std::collections::{HashMap, HashSet};
+
+struct Tarjan<'a> {
+    edges: &'a HashMap<String, Vec<String>>,
+    index: HashMap<&'a str, usize>,
+    lowlink: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: HashMap<&'a str, bool>,
+    components: HashMap<String, usize>,
+    next_component: usize,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, node: &'a str) {
+        let i = self.index.len();
+        self.index.insert(node, i);
+        self.lowlink.insert(node, i);
+        self.stack.push(node);
+        self.on_stack.insert(node, true);
+        for next in self.edges.get(node).into_iter().flatten() {
+            let next = next.as_str();
+            if !self.index.contains_key(next) {
+                self.visit(next);
+                let low = self.lowlink[node].min(self.lowlink[next]);
+                self.lowlink.insert(node, low);
+            } else if self.on_stack.get(next).cloned().unwrap_or(false) {
+                let low = self.lowlink[node].min(self.index[next]);
+                self.lowlink.insert(node, low);
+            }
+        }
+        if self.lowlink[node] == self.index[node] {
+            loop {
+                let n = self.stack.pop().unwrap();
+                self.on_stack.insert(n, false);
+                self.components.insert(n.to_owned(), self.next_component);
+                if n == node {
+                    break;
+                }
+            }
+            self.next_component += 1;
+        }
+    }
+}
+
+/// Maps every node of the graph to the id of its strongly connected
+/// component. Nodes that only appear as edge targets are included.
+pub fn components(edges: &HashMap<String, Vec<String>>) -> HashMap<String, usize> {
+    let mut tarjan = Tarjan {
+        edges,
+        index: HashMap::new(),
+        lowlink: HashMap::new(),
+        stack: Vec::new(),
+        on_stack: HashMap::new(),
+        components: HashMap::new(),
+        next_component: 0,
+    };
+    let mut nodes: Vec<&String> = edges.keys().chain(edges.values().flatten()).collect();
+    // Visiting in a fixed order keeps the component ids stable.
+    nodes.sort();
+    for node in nodes {
+        if !tarjan.index.contains_key(node.as_str()) {
+            tarjan.visit(node);
+        }
+    }
+    tarjan.components
+}
+
+/// Picks edges that, once removed, leave the graph without cycles: the back
+/// edges of a depth-first search within each strongly connected component.
+/// Edges between components are never part of a cycle and aren't picked.
+pub fn cycle_edges(edges: &HashMap<String, Vec<String>>) -> HashSet<(String, String)> {
+    fn visit<'a>(
+        node: &'a str,
+        edges: &'a HashMap<String, Vec<String>>,
+        components: &HashMap<String, usize>,
+        visited: &mut HashSet<&'a str>,
+        on_path: &mut HashSet<&'a str>,
+        cut: &mut HashSet<(String, String)>,
+    ) {
+        visited.insert(node);
+        on_path.insert(node);
+        for next in edges.get(node).into_iter().flatten() {
+            if components[next] != components[node] {
+                continue;
+            }
+            if on_path.contains(next.as_str()) {
+                cut.insert((node.to_owned(), next.clone()));
+            } else if !visited.contains(next.as_str()) {
+                visit(next, edges, components, visited, on_path, cut);
+            }
+        }
+        on_path.remove(node);
+    }
+
+    let components = components(edges);
+    let mut nodes: Vec<&String> = edges.keys().collect();
+    nodes.sort();
+    let mut visited = HashSet::new();
+    let mut cut = HashSet::new();
+    for node in nodes {
+        if !visited.contains(node.as_str()) {
+            visit(node, edges, &components, &mut visited, &mut HashSet::new(), &mut cut);
+        }
+    }
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+        let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+        for &(from, to) in edges {
+            graph.entry(from.to_owned()).or_default().push(to.to_owned());
+        }
+        graph
+    }
+
+    fn cut(edges: &[(&str, &str)]) -> HashSet<(String, String)> {
+        edges.iter().map(|&(from, to)| (from.to_owned(), to.to_owned())).collect()
+    }
+
+    #[test]
+    fn self_cycle() {
+        let graph = graph(&[("a", "a"), ("a", "b")]);
+        let components = components(&graph);
+        assert_ne!(components["a"], components["b"]);
+        assert_eq!(cycle_edges(&graph), cut(&[("a", "a")]));
+    }
+
+    #[test]
+    fn indirect_cycle() {
+        let graph = graph(&[("a", "b"), ("b", "a"), ("b", "c")]);
+        let components = components(&graph);
+        assert_eq!(components["a"], components["b"]);
+        assert_ne!(components["a"], components["c"]);
+        // The search starts from `a`, so the edge back to it is cut.
+        assert_eq!(cycle_edges(&graph), cut(&[("b", "a")]));
+    }
+
+    #[test]
+    fn no_cycle() {
+        let graph = graph(&[("a", "b"), ("a", "c"), ("b", "c")]);
+        let components = components(&graph);
+        assert_eq!(components.len(), 3);
+        assert_ne!(components["a"], components["b"]);
+        assert_ne!(components["b"], components["c"]);
+        assert!(cycle_edges(&graph).is_empty());
+    }
+
+    #[test]
+    fn cycles_sharing_a_node() {
+        let graph = graph(&[("a", "b"), ("b", "a"), ("b", "c"), ("c", "b")]);
+        let components = components(&graph);
+        assert_eq!(components["a"], components["b"]);
+        assert_eq!(components["b"], components["c"]);
+        assert_eq!(cycle_edges(&graph), cut(&[("b", "a"), ("c", "b")]));
+    }
+}
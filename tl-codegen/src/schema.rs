@@ -0,0 +1,280 @@
+//! Parsed representation of a tl file.
+//!
+//! This is what the code generator works from, and can be used to write
+//! other generators on top of the same parser.
+
+use pest::Parser;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+
+use super::{Rule, TlParser};
+
+/// Error returned when a tl file cannot be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for ParseError {}
+
+const BUILTINS: &[&str] = &["double", "string", "int32", "int53", "int64", "Bool", "bytes"];
+
+/// The type of a parameter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TypeRef {
+    /// One of `double`, `string`, `int32`, `int53`, `int64`, `Bool` or
+    /// `bytes`.
+    Builtin(String),
+    /// A class, e.g. `ChatList`, which may be any of its constructors.
+    Class(String),
+    /// A bare constructor, e.g. `formattedText`.
+    Constructor(String),
+    Vector(Box<TypeRef>),
+}
+
+impl TypeRef {
+    fn from_pair(pair: pest::iterators::Pair<Rule>) -> TypeRef {
+        match pair.as_rule() {
+            Rule::vector => {
+                let inner = pair.into_inner().next().unwrap().into_inner().next().unwrap();
+                TypeRef::Vector(Box::new(TypeRef::from_pair(inner)))
+            }
+            Rule::ident => {
+                let name = pair.as_str().to_owned();
+                if BUILTINS.contains(&pair.as_str()) {
+                    TypeRef::Builtin(name)
+                } else if name.starts_with(char::is_uppercase) {
+                    TypeRef::Class(name)
+                } else {
+                    TypeRef::Constructor(name)
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for TypeRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeRef::Vector(t) => write!(f, "vector<{}>", t),
+            TypeRef::Builtin(n) | TypeRef::Class(n) | TypeRef::Constructor(n) => f.write_str(n),
+        }
+    }
+}
+
+/// A field of a constructor or a parameter of a function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub typeid: TypeRef,
+    /// Whether the value may be null, according to its documentation.
+    pub optional: bool,
+    /// Whether 0 or an empty string may be passed to mean none, according to
+    /// the documentation.
+    pub empty_allowed: bool,
+    pub doc: String,
+}
+
+/// A constructor or a function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub params: Vec<Param>,
+    /// The class of a constructor, or the return type of a function.
+    pub result: String,
+    pub doc: String,
+}
+
+/// A class, i.e. the set of constructors sharing the same result type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub name: String,
+    /// Names of the constructors, in the order they are defined.
+    pub constructors: Vec<String>,
+    pub doc: String,
+}
+
+/// A whole tl file. All lists are in the order of the file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    pub types: Vec<Definition>,
+    pub classes: Vec<Class>,
+    pub methods: Vec<Definition>,
+}
+
+impl Schema {
+    pub fn constructor(&self, name: &str) -> Option<&Definition> {
+        self.types.iter().find(|t| t.name == name)
+    }
+
+    pub fn class(&self, name: &str) -> Option<&Class> {
+        self.classes.iter().find(|c| c.name == name)
+    }
+
+    pub fn method(&self, name: &str) -> Option<&Definition> {
+        self.methods.iter().find(|m| m.name == name)
+    }
+}
+
+struct DocInfo {
+    doc: String,
+    class: Option<(String, String)>,
+    params: HashMap<String, String>,
+}
+
+fn extract_docinfo(pair: pest::iterators::Pair<Rule>) -> DocInfo {
+    let mut info = DocInfo {
+        doc: String::new(),
+        class: None,
+        params: HashMap::new(),
+    };
+    let mut class = None;
+    for p in pair.into_inner() {
+        let mut pairs = p.into_inner();
+        let name = pairs.next().unwrap().as_str();
+        let descr = pairs.next().unwrap().as_str().replace("\n//-", " ");
+        let descr = descr.trim().to_owned();
+        if name == "description" {
+            // A `@class` line is followed by the description of the class,
+            // not of the type being defined.
+            match class.take() {
+                Some(classname) => info.class = Some((classname, descr)),
+                None => info.doc = descr,
+            }
+        } else if name == "class" {
+            class = Some(descr);
+        } else {
+            let n = if name == "param_description" {
+                "description"
+            } else {
+                name
+            };
+            info.params.insert(n.to_owned(), descr);
+        }
+    }
+    info
+}
+
+fn is_empty_allowed(doc: &str) -> bool {
+    doc.contains(" or 0")
+        || doc.contains(" 0 if")
+        || doc.contains("use 0")
+        || doc.contains("pass 0")
+        || doc.contains("may be empty")
+        || doc.contains("can be empty")
+        || doc.contains("if empty")
+}
+
+fn is_optional(doc: &str) -> bool {
+    doc.contains("may be null")
+        || doc.contains("only available to bots")
+        || doc.contains("bots only")
+        || doc.contains("or null")
+}
+
+/// Returns the first name in `typeid` that the schema does not define.
+fn undefined<'a>(typeid: &'a TypeRef, types: &HashSet<&str>, classes: &HashSet<&str>) -> Option<&'a str> {
+    match typeid {
+        TypeRef::Vector(t) => undefined(t, types, classes),
+        TypeRef::Builtin(_) => None,
+        TypeRef::Class(n) if classes.contains(n.as_str()) => None,
+        TypeRef::Constructor(n) if types.contains(n.as_str()) => None,
+        TypeRef::Class(n) | TypeRef::Constructor(n) => Some(n),
+    }
+}
+
+/// Parses a tl file, checking that every type it refers to is defined.
+pub fn parse(src: &str) -> Result<Schema, ParseError> {
+    let pairs = TlParser::parse(Rule::tl, src).map_err(|e| ParseError {
+        message: e.to_string(),
+    })?;
+    let mut schema = Schema::default();
+    let mut class_docs = HashMap::new();
+    let mut functions = false;
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::section => {
+                functions = true;
+            }
+            Rule::definition => {
+                let mut pairs = pair.into_inner();
+                let mut docinfo = extract_docinfo(pairs.next().unwrap());
+                if let Some((class, doc)) = docinfo.class.take() {
+                    class_docs.insert(class, doc);
+                }
+                let mut pairs = pairs.next().unwrap().into_inner();
+                let name = pairs.next().unwrap().as_str().to_owned();
+                let params = pairs
+                    .next()
+                    .unwrap()
+                    .into_inner()
+                    .map(|p| {
+                        let mut pairs = p.into_inner();
+                        let name = pairs.next().unwrap().as_str().to_owned();
+                        let typeid = pairs.next().unwrap().into_inner().next().unwrap();
+                        let doc = docinfo.params.remove(&name).unwrap_or_default();
+                        Param {
+                            name,
+                            typeid: TypeRef::from_pair(typeid),
+                            optional: is_optional(&doc),
+                            empty_allowed: is_empty_allowed(&doc),
+                            doc,
+                        }
+                    })
+                    .collect();
+                let definition = Definition {
+                    name,
+                    params,
+                    result: pairs.next().unwrap().as_str().to_owned(),
+                    doc: docinfo.doc,
+                };
+                if functions {
+                    schema.methods.push(definition);
+                } else {
+                    match schema.classes.iter_mut().find(|c| c.name == definition.result) {
+                        Some(class) => class.constructors.push(definition.name.clone()),
+                        None => schema.classes.push(Class {
+                            name: definition.result.clone(),
+                            constructors: vec![definition.name.clone()],
+                            doc: String::new(),
+                        }),
+                    }
+                    schema.types.push(definition);
+                }
+            }
+            _ => {}
+        }
+    }
+    for class in &mut schema.classes {
+        if let Some(doc) = class_docs.remove(&class.name) {
+            class.doc = doc;
+        }
+    }
+
+    {
+        let types = schema.types.iter().map(|t| t.name.as_str()).collect();
+        let classes = schema.classes.iter().map(|c| c.name.as_str()).collect();
+        for definition in schema.types.iter().chain(&schema.methods) {
+            let result = TypeRef::Class(definition.result.clone());
+            let typeids = definition.params.iter().map(|p| &p.typeid);
+            if let Some(t) = typeids
+                .chain(Some(&result))
+                .filter_map(|t| undefined(t, &types, &classes))
+                .next()
+            {
+                return Err(ParseError {
+                    message: format!("unknown type `{}` in `{}`", t, definition.name),
+                });
+            }
+        }
+    }
+    Ok(schema)
+}
@@ -0,0 +1,44 @@
+//! Exit codes of the `tl-diff` binary.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn tl_diff(args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_tl-diff"))
+        .args(args)
+        .output()
+        .expect("cannot run tl-diff")
+        .status
+        .code()
+}
+
+#[test]
+fn same() {
+    let old = fixture("old.tl");
+    let old = old.to_str().unwrap();
+    assert_eq!(tl_diff(&[old, old]), Some(0));
+    assert_eq!(tl_diff(&["--json", old, old]), Some(0));
+}
+
+#[test]
+fn different() {
+    let (old, new) = (fixture("old.tl"), fixture("new.tl"));
+    let (old, new) = (old.to_str().unwrap(), new.to_str().unwrap());
+    assert_eq!(tl_diff(&[old, new]), Some(1));
+    assert_eq!(tl_diff(&["--json", old, new]), Some(1));
+}
+
+#[test]
+fn errors() {
+    let old = fixture("old.tl");
+    let old = old.to_str().unwrap();
+    let missing = fixture("missing.tl");
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    assert_eq!(tl_diff(&[old]), Some(2));
+    assert_eq!(tl_diff(&[old, missing.to_str().unwrap()]), Some(2));
+    assert_eq!(tl_diff(&[old, manifest.to_str().unwrap()]), Some(2));
+}